@@ -0,0 +1,308 @@
+use async_openai::config::OpenAIConfig;
+use async_openai::error::{ApiError, OpenAIError};
+use async_openai::types::{
+    ChatChoice, ChatCompletionMessageToolCall, ChatCompletionRequestMessage,
+    ChatCompletionRequestMessageContentPart, ChatCompletionRequestUserMessageContent,
+    ChatCompletionResponseMessage, ChatCompletionResponseStream, ChatCompletionToolType,
+    CompletionUsage, CreateChatCompletionRequest, CreateChatCompletionResponse, FinishReason,
+    FunctionCall, Role,
+};
+use async_openai::Client;
+use futures::future::BoxFuture;
+use serde_json::{json, Value};
+
+/// A chat completion API.
+///
+/// Requests and responses use the OpenAI types, which is also how the conversation is stored.
+/// Other APIs translate them to and from their own formats.
+pub trait Backend: Send + Sync {
+    /// Send a request, and wait for the complete response
+    fn send_chat_request<'a>(
+        &'a self,
+        request: &'a CreateChatCompletionRequest,
+    ) -> BoxFuture<'a, Result<CreateChatCompletionResponse, OpenAIError>>;
+
+    /// Send a request, and stream the response
+    fn send_chat_request_stream<'a>(
+        &'a self,
+        request: &'a CreateChatCompletionRequest,
+    ) -> BoxFuture<'a, Result<ChatCompletionResponseStream, OpenAIError>>;
+
+    /// Whether `send_chat_request_stream` is supported
+    fn supports_streaming(&self) -> bool {
+        true
+    }
+}
+
+impl Backend for Client<OpenAIConfig> {
+    fn send_chat_request<'a>(
+        &'a self,
+        request: &'a CreateChatCompletionRequest,
+    ) -> BoxFuture<'a, Result<CreateChatCompletionResponse, OpenAIError>> {
+        Box::pin(async move { self.chat().create(request.clone()).await })
+    }
+
+    fn send_chat_request_stream<'a>(
+        &'a self,
+        request: &'a CreateChatCompletionRequest,
+    ) -> BoxFuture<'a, Result<ChatCompletionResponseStream, OpenAIError>> {
+        Box::pin(async move { self.chat().create_stream(request.clone()).await })
+    }
+}
+
+/// Anthropic's messages API
+pub struct AnthropicBackend {
+    http_client: reqwest::Client,
+    api_key: String,
+}
+
+impl AnthropicBackend {
+    const URL: &'static str = "https://api.anthropic.com/v1/messages";
+    const API_VERSION: &'static str = "2023-06-01";
+    /// The messages API requires an upper bound of the response length
+    const MAX_TOKENS: u32 = 4096;
+
+    pub fn new(http_client: reqwest::Client, api_key: String) -> Self {
+        Self {
+            http_client,
+            api_key,
+        }
+    }
+
+    async fn send(
+        &self,
+        request: &CreateChatCompletionRequest,
+    ) -> Result<CreateChatCompletionResponse, OpenAIError> {
+        let response = self
+            .http_client
+            .post(Self::URL)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", Self::API_VERSION)
+            .json(&Self::translate_request(request))
+            .send()
+            .await?;
+        let status = response.status();
+        let body = response.bytes().await?;
+        let body: Value = serde_json::from_slice(&body).map_err(OpenAIError::JSONDeserialize)?;
+        if !status.is_success() {
+            return Err(Self::translate_error(status, &body["error"]));
+        }
+        Ok(Self::translate_response(request, body))
+    }
+
+    /// Translate an Anthropic error into an OpenAI API error.
+    /// Error types are kept as is, e.g. `rate_limit_error` or `overloaded_error`, and key errors
+    /// get the OpenAI error codes, so they fail over to the next key in the same way.
+    fn translate_error(status: reqwest::StatusCode, error: &Value) -> OpenAIError {
+        let r#type = error["type"].as_str();
+        let code = match r#type {
+            Some("authentication_error" | "permission_error") => json!("invalid_api_key"),
+            Some("rate_limit_error") => json!("rate_limit_exceeded"),
+            _ => json!(status.as_u16()),
+        };
+        OpenAIError::ApiError(ApiError {
+            message: error["message"]
+                .as_str()
+                .map(|m| m.to_owned())
+                .unwrap_or_else(|| status.to_string()),
+            r#type: r#type.map(|t| t.to_owned()),
+            param: None,
+            code: Some(code),
+        })
+    }
+
+    /// Translate an OpenAI chat request into an Anthropic messages request.
+    /// System messages become the `system` prompt, tool calls become `tool_use` blocks,
+    /// and tool results become `tool_result` blocks of a user message.
+    #[allow(deprecated)]
+    fn translate_request(request: &CreateChatCompletionRequest) -> Value {
+        let mut system = vec![];
+        let mut messages: Vec<(&str, Vec<Value>)> = vec![];
+        for message in &request.messages {
+            let (role, blocks) = match message {
+                ChatCompletionRequestMessage::System(m) => {
+                    system.push(m.content.as_str());
+                    continue;
+                }
+                ChatCompletionRequestMessage::User(m) => {
+                    let text = match &m.content {
+                        ChatCompletionRequestUserMessageContent::Text(text) => text.clone(),
+                        ChatCompletionRequestUserMessageContent::Array(parts) => parts
+                            .iter()
+                            .filter_map(|part| match part {
+                                ChatCompletionRequestMessageContentPart::Text(t) => {
+                                    Some(t.text.as_str())
+                                }
+                                ChatCompletionRequestMessageContentPart::Image(_) => None,
+                            })
+                            .collect::<Vec<_>>()
+                            .join("\n"),
+                    };
+                    ("user", vec![json!({ "type": "text", "text": text })])
+                }
+                ChatCompletionRequestMessage::Assistant(m) => {
+                    let text = m.content.iter().filter(|c| !c.is_empty());
+                    let text = text.map(|text| json!({ "type": "text", "text": text }));
+                    let tool_uses = m.tool_calls.iter().flatten().map(|call| {
+                        let input = serde_json::from_str::<Value>(&call.function.arguments)
+                            .unwrap_or_else(|_| json!({}));
+                        json!({
+                            "type": "tool_use",
+                            "id": call.id,
+                            "name": call.function.name,
+                            "input": input,
+                        })
+                    });
+                    ("assistant", text.chain(tool_uses).collect())
+                }
+                ChatCompletionRequestMessage::Tool(m) => (
+                    "user",
+                    vec![json!({
+                        "type": "tool_result",
+                        "tool_use_id": m.tool_call_id,
+                        "content": m.content,
+                    })],
+                ),
+                ChatCompletionRequestMessage::Function(_) => continue,
+            };
+            if blocks.is_empty() {
+                continue;
+            }
+            // Roles must alternate, so merge consecutive messages of the same role
+            match messages.last_mut() {
+                Some((last_role, last_blocks)) if *last_role == role => last_blocks.extend(blocks),
+                _ => messages.push((role, blocks)),
+            }
+        }
+        let tools = request
+            .tools
+            .iter()
+            .flatten()
+            .map(|tool| {
+                json!({
+                    "name": tool.function.name,
+                    "description": tool.function.description,
+                    "input_schema": tool.function.parameters.clone().unwrap_or_else(|| json!({ "type": "object" })),
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut body = json!({
+            "model": request.model,
+            "max_tokens": request.max_tokens.map(u32::from).unwrap_or(Self::MAX_TOKENS),
+            "messages": messages
+                .into_iter()
+                .map(|(role, content)| json!({ "role": role, "content": content }))
+                .collect::<Vec<_>>(),
+        });
+        if !system.is_empty() {
+            body["system"] = json!(system.join("\n\n"));
+        }
+        if !tools.is_empty() {
+            body["tools"] = json!(tools);
+        }
+        body
+    }
+
+    /// Translate an Anthropic messages response into an OpenAI chat response
+    #[allow(deprecated)]
+    fn translate_response(
+        request: &CreateChatCompletionRequest,
+        body: Value,
+    ) -> CreateChatCompletionResponse {
+        let mut content: Option<String> = None;
+        let mut tool_calls = vec![];
+        for block in body["content"].as_array().into_iter().flatten() {
+            match block["type"].as_str() {
+                Some("text") => content
+                    .get_or_insert_with(String::new)
+                    .push_str(block["text"].as_str().unwrap_or_default()),
+                Some("tool_use") => tool_calls.push(ChatCompletionMessageToolCall {
+                    id: block["id"].as_str().unwrap_or_default().to_owned(),
+                    r#type: ChatCompletionToolType::Function,
+                    function: FunctionCall {
+                        name: block["name"].as_str().unwrap_or_default().to_owned(),
+                        arguments: block["input"].to_string(),
+                    },
+                }),
+                _ => {}
+            }
+        }
+        let finish_reason = match body["stop_reason"].as_str() {
+            Some("tool_use") => Some(FinishReason::ToolCalls),
+            Some("max_tokens") => Some(FinishReason::Length),
+            Some(_) => Some(FinishReason::Stop),
+            None => None,
+        };
+        let prompt_tokens = body["usage"]["input_tokens"].as_u64().unwrap_or_default() as u32;
+        let completion_tokens = body["usage"]["output_tokens"].as_u64().unwrap_or_default() as u32;
+        CreateChatCompletionResponse {
+            id: body["id"].as_str().unwrap_or_default().to_owned(),
+            choices: vec![ChatChoice {
+                index: 0,
+                message: ChatCompletionResponseMessage {
+                    content,
+                    tool_calls: (!tool_calls.is_empty()).then_some(tool_calls),
+                    role: Role::Assistant,
+                    function_call: None,
+                },
+                finish_reason,
+                logprobs: None,
+            }],
+            created: chrono::Utc::now().timestamp() as u32,
+            model: body["model"].as_str().unwrap_or(&request.model).to_owned(),
+            system_fingerprint: None,
+            object: "chat.completion".to_owned(),
+            usage: Some(CompletionUsage {
+                prompt_tokens,
+                completion_tokens,
+                total_tokens: prompt_tokens + completion_tokens,
+            }),
+        }
+    }
+}
+
+impl Backend for AnthropicBackend {
+    fn send_chat_request<'a>(
+        &'a self,
+        request: &'a CreateChatCompletionRequest,
+    ) -> BoxFuture<'a, Result<CreateChatCompletionResponse, OpenAIError>> {
+        Box::pin(self.send(request))
+    }
+
+    fn send_chat_request_stream<'a>(
+        &'a self,
+        _request: &'a CreateChatCompletionRequest,
+    ) -> BoxFuture<'a, Result<ChatCompletionResponseStream, OpenAIError>> {
+        Box::pin(async {
+            Err(OpenAIError::InvalidArgument(
+                "streaming is not supported by the Anthropic backend".to_owned(),
+            ))
+        })
+    }
+
+    fn supports_streaming(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_code(status: u16, r#type: &str) -> Value {
+        let error = json!({ "type": r#type, "message": "error" });
+        let status = reqwest::StatusCode::from_u16(status).unwrap();
+        match AnthropicBackend::translate_error(status, &error) {
+            OpenAIError::ApiError(e) => e.code.unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn key_errors_use_openai_codes() {
+        assert_eq!(error_code(401, "authentication_error"), "invalid_api_key");
+        assert_eq!(error_code(403, "permission_error"), "invalid_api_key");
+        assert_eq!(error_code(429, "rate_limit_error"), "rate_limit_exceeded");
+        assert_eq!(error_code(529, "overloaded_error"), 529);
+    }
+}
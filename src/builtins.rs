@@ -1,27 +1,198 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::config;
+
+/// Names of all builtin commands, for `type`
+const BUILTIN_NAMES: [&str; 7] = ["exit", "env", "cd", "mkdir", "test", "[", "type"];
+
+/// The working directory before the last `cd`, for `cd -`
+static PREVIOUS_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Commands with shell syntax (e.g. `mkdir a && cd a`) are left to bash
+fn has_shell_syntax(command: &str) -> bool {
+    command.contains(['&', '|', ';', '<', '>', '$', '`', '*'])
+}
+
+/// Like `has_shell_syntax`, but allows `$VAR` and `${VAR}`, which builtins expand themselves
+fn has_shell_syntax_except_variables(command: &str) -> bool {
+    command.contains("$(") || has_shell_syntax(&command.replace('$', ""))
+}
+
+/// Expand a leading `~` and `$VAR` / `${VAR}` in a path argument
+fn expand_path(path: &str) -> anyhow::Result<PathBuf> {
+    let mut expanded = String::new();
+    let mut rest = path;
+    if rest == "~" || rest.starts_with("~/") {
+        let home = home::home_dir().ok_or_else(|| anyhow::anyhow!("HOME not set"))?;
+        expanded.push_str(&home.to_string_lossy());
+        rest = &rest[1..];
+    }
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let (name, remaining) = match rest.strip_prefix('{') {
+            Some(braced) => {
+                let end = braced
+                    .find('}')
+                    .ok_or_else(|| anyhow::anyhow!("bad substitution: missing '}}'"))?;
+                (&braced[..end], &braced[end + 1..])
+            }
+            None => {
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            }
+        };
+        if name.is_empty() {
+            expanded.push('$');
+        } else {
+            let value =
+                std::env::var(name).map_err(|_| anyhow::anyhow!("{}: unset variable", name))?;
+            expanded.push_str(&value);
+        }
+        rest = remaining;
+    }
+    expanded.push_str(rest);
+    Ok(PathBuf::from(expanded))
+}
+
+/// Evaluate the expression of a `test` / `[` command. Returns `None` for unsupported expressions.
+fn eval_test_expr(args: &[&str]) -> Option<bool> {
+    let args = match args {
+        ["[", rest @ .., "]"] => rest,
+        ["test", rest @ ..] => rest,
+        _ => return None,
+    };
+    match args {
+        [] => Some(false),
+        [s] => Some(!s.is_empty()),
+        ["-f", path] => Some(std::path::Path::new(path).is_file()),
+        ["-d", path] => Some(std::path::Path::new(path).is_dir()),
+        ["-e", path] => Some(std::path::Path::new(path).exists()),
+        ["-z", s] => Some(s.is_empty()),
+        ["-n", s] => Some(!s.is_empty()),
+        [a, "=", b] | [a, "==", b] => Some(a == b),
+        [a, "!=", b] => Some(a != b),
+        [a, "-eq", b] => Some(a.parse::<i64>().ok()? == b.parse::<i64>().ok()?),
+        [a, "-ne", b] => Some(a.parse::<i64>().ok()? != b.parse::<i64>().ok()?),
+        _ => None,
+    }
+}
+
 pub fn is_built_in_command(command: &str) -> bool {
     let words = command.split_whitespace().collect::<Vec<&str>>();
     match words[0] {
         "exit" => true,
-        "cd" => words.len() == 2,
+        // `env VAR=val COMMAND` runs a command, so it is left to bash and needs confirmation
+        "env" => words.len() == 1,
+        "cd" => words.len() <= 2 && !has_shell_syntax_except_variables(command),
+        "mkdir" => {
+            let paths = words[1..].iter().filter(|w| **w != "-p");
+            !has_shell_syntax(command)
+                && paths.clone().count() > 0
+                && paths.clone().all(|w| !w.starts_with('-'))
+        }
+        "type" => words.len() >= 2 && !has_shell_syntax(command),
+        "test" | "[" => {
+            let Ok(words) = shellwords::split(command) else {
+                return false;
+            };
+            let words = words.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+            !has_shell_syntax(command) && eval_test_expr(&words).is_some()
+        }
         _ => false,
     }
 }
 
-pub fn execute_built_in_command(command: &str) -> anyhow::Result<()> {
+/// Change the working directory, and remember the old one for `cd -`
+pub fn change_dir(path: &Path) -> std::io::Result<()> {
+    let current = std::env::current_dir().ok();
+    std::env::set_current_dir(path)?;
+    *PREVIOUS_DIR.lock().unwrap() = current;
+    Ok(())
+}
+
+/// Whether a built-in command changes the file system, and so needs confirmation like bash commands
+pub fn needs_confirmation(command: &str) -> bool {
+    command.split_whitespace().next() == Some("mkdir")
+}
+
+/// Execute a built-in command and return its exit status and stdout.
+/// `env` hides the variables matching `sensitive_env_patterns` or the default secret patterns.
+pub fn execute_built_in_command(
+    command: &str,
+    sensitive_env_patterns: &[String],
+) -> anyhow::Result<(i32, String)> {
     let words = shellwords::split(command).unwrap();
     match words[0].as_str() {
         "exit" => std::process::exit(0),
+        "env" => {
+            let vars = config::non_sensitive_env_vars(sensitive_env_patterns)
+                .into_iter()
+                .collect::<BTreeMap<_, _>>()
+                .into_iter()
+                .map(|(key, value)| format!("{}={}\n", key, value))
+                .collect::<String>();
+            Ok((0, vars))
+        }
         "cd" => {
-            let args: Vec<&str> = words[1..].iter().map(|s| s.as_str()).collect();
-            if args.len() < 2 {
-                anyhow::bail!("cd: missing argument");
+            let mut stdout = String::new();
+            let path = match words.get(1).map(|s| s.as_str()) {
+                None => home::home_dir().ok_or_else(|| anyhow::anyhow!("cd: HOME not set"))?,
+                Some("-") => {
+                    let previous = PREVIOUS_DIR.lock().unwrap().clone();
+                    let previous = previous.ok_or_else(|| anyhow::anyhow!("cd: OLDPWD not set"))?;
+                    // Like bash, print the new directory
+                    stdout = format!("{}\n", previous.display());
+                    previous
+                }
+                Some(path) => expand_path(path).map_err(|e| anyhow::anyhow!("cd: {}", e))?,
+            };
+            if let Err(e) = change_dir(&path) {
+                anyhow::bail!("cd: {}", e);
+            }
+            Ok((0, stdout))
+        }
+        "mkdir" => {
+            let parents = words[1..].iter().any(|w| w == "-p");
+            let paths = words[1..].iter().filter(|w| *w != "-p");
+            for path in paths {
+                let result = if parents {
+                    std::fs::create_dir_all(path)
+                } else {
+                    std::fs::create_dir(path)
+                };
+                if let Err(e) = result {
+                    anyhow::bail!("mkdir: cannot create directory '{}': {}", path, e);
+                }
             }
-            let path = args[1];
-            match std::env::set_current_dir(path) {
-                Ok(_) => {}
-                Err(e) => anyhow::bail!("cd: {}", e),
+            Ok((0, String::new()))
+        }
+        "type" => {
+            let mut stdout = String::new();
+            let mut status = 0;
+            for name in &words[1..] {
+                if BUILTIN_NAMES.contains(&name.as_str()) {
+                    stdout.push_str(&format!("{} is a gptsh builtin\n", name));
+                } else if let Ok(path) = which::which(name) {
+                    stdout.push_str(&format!("{} is {}\n", name, path.display()));
+                } else {
+                    stdout.push_str(&format!("{} not found\n", name));
+                    status = 1;
+                }
+            }
+            Ok((status, stdout))
+        }
+        "test" | "[" => {
+            let words = words.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+            match eval_test_expr(&words) {
+                Some(true) => Ok((0, String::new())),
+                Some(false) => Ok((1, String::new())),
+                None => anyhow::bail!("{}: unsupported expression", words[0]),
             }
-            Ok(())
         }
         _ => anyhow::bail!("Command not found: {}", command),
     }
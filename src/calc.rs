@@ -0,0 +1,239 @@
+/// A tiny calculator for arithmetic expressions.
+///
+/// Supports `+ - * / % ^`, parentheses, the constants `pi` and `e`,
+/// and common functions like `sqrt(x)`, `ln(x)` and `sin(x)`.
+pub fn eval(expr: &str) -> anyhow::Result<f64> {
+    let mut parser = Parser {
+        chars: expr.chars().filter(|c| !c.is_whitespace()).collect(),
+        pos: 0,
+    };
+    let value = parser.expr()?;
+    if parser.pos != parser.chars.len() {
+        anyhow::bail!(
+            "unexpected '{}' at position {}",
+            parser.chars[parser.pos],
+            parser.pos
+        );
+    }
+    Ok(value)
+}
+
+/// Format a result without a trailing `.0` for integers
+pub fn format_result(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        format!("{}", value)
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expr(&mut self) -> anyhow::Result<f64> {
+        let mut value = self.term()?;
+        loop {
+            if self.eat('+') {
+                value += self.term()?;
+            } else if self.eat('-') {
+                value -= self.term()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn term(&mut self) -> anyhow::Result<f64> {
+        let mut value = self.unary()?;
+        loop {
+            if self.eat('*') {
+                value *= self.unary()?;
+            } else if self.eat('/') {
+                value /= self.divisor()?;
+            } else if self.eat('%') {
+                value %= self.divisor()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn divisor(&mut self) -> anyhow::Result<f64> {
+        let value = self.unary()?;
+        if value == 0.0 {
+            anyhow::bail!("division by zero");
+        }
+        Ok(value)
+    }
+
+    /// Unary minus binds looser than `^`, so `-2^2` is `-4`
+    fn unary(&mut self) -> anyhow::Result<f64> {
+        if self.eat('-') {
+            return Ok(-self.unary()?);
+        }
+        if self.eat('+') {
+            return self.unary();
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> anyhow::Result<f64> {
+        let base = self.primary()?;
+        if self.eat('^') {
+            // Right associative, and the exponent may be negative
+            return Ok(base.powf(self.unary()?));
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> anyhow::Result<f64> {
+        match self.peek() {
+            Some('(') => {
+                self.pos += 1;
+                let value = self.expr()?;
+                if !self.eat(')') {
+                    anyhow::bail!("missing ')'");
+                }
+                Ok(value)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_digit() || c == '.') {
+                    self.pos += 1;
+                }
+                let number = self.chars[start..self.pos].iter().collect::<String>();
+                number
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("invalid number '{}'", number))
+            }
+            Some(c) if c.is_ascii_alphabetic() => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric()) {
+                    self.pos += 1;
+                }
+                let name = self.chars[start..self.pos].iter().collect::<String>();
+                match name.as_str() {
+                    "pi" => return Ok(std::f64::consts::PI),
+                    "e" => return Ok(std::f64::consts::E),
+                    _ => {}
+                }
+                if !self.eat('(') {
+                    anyhow::bail!("unknown constant '{}'", name);
+                }
+                let arg = self.expr()?;
+                if !self.eat(')') {
+                    anyhow::bail!("missing ')'");
+                }
+                let f = match name.as_str() {
+                    "sqrt" => f64::sqrt,
+                    "abs" => f64::abs,
+                    "ln" => f64::ln,
+                    "log" | "log10" => f64::log10,
+                    "log2" => f64::log2,
+                    "exp" => f64::exp,
+                    "sin" => f64::sin,
+                    "cos" => f64::cos,
+                    "tan" => f64::tan,
+                    "floor" => f64::floor,
+                    "ceil" => f64::ceil,
+                    "round" => f64::round,
+                    _ => anyhow::bail!("unknown function '{}'", name),
+                };
+                Ok(f(arg))
+            }
+            Some(c) => anyhow::bail!("unexpected '{}' at position {}", c, self.pos),
+            None => anyhow::bail!("unexpected end of expression"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_eval(expr: &str, expected: f64) {
+        let value = eval(expr).unwrap();
+        assert!(
+            (value - expected).abs() < 1e-9,
+            "{} = {}, expected {}",
+            expr,
+            value,
+            expected
+        );
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eval("1 + 2 * 3", 7.0);
+        assert_eval("2 * 3 ^ 2", 18.0);
+        assert_eval("10 - 4 / 2", 8.0);
+        assert_eval("7 % 4 + 1", 4.0);
+    }
+
+    #[test]
+    fn associativity() {
+        assert_eval("10 - 4 - 3", 3.0);
+        assert_eval("64 / 4 / 2", 8.0);
+        assert_eval("2 ^ 3 ^ 2", 512.0);
+    }
+
+    #[test]
+    fn unary_minus() {
+        assert_eval("-3", -3.0);
+        assert_eval("--3", 3.0);
+        assert_eval("2 * -3", -6.0);
+        assert_eval("-2 ^ 2", -4.0);
+        assert_eval("2 ^ -1", 0.5);
+        assert_eval("+4", 4.0);
+    }
+
+    #[test]
+    fn parentheses() {
+        assert_eval("(1 + 2) * 3", 9.0);
+        assert_eval("(-2) ^ 2", 4.0);
+        assert_eval("((2))", 2.0);
+        assert_eval("sqrt(16) + abs(-1)", 5.0);
+        assert_eval("2 * pi", std::f64::consts::TAU);
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(eval("1 / 0").unwrap_err().to_string(), "division by zero");
+        assert_eq!(
+            eval("1 % (2 - 2)").unwrap_err().to_string(),
+            "division by zero"
+        );
+    }
+
+    #[test]
+    fn malformed_input() {
+        for expr in [
+            "", "1 +", "(1 + 2", "1 + 2)", "1..2", "2 * * 3", "foo", "foo(1)", "sqrt(4", "1 $ 2",
+            ")",
+        ] {
+            assert!(eval(expr).is_err(), "{:?} should be an error", expr);
+        }
+    }
+
+    #[test]
+    fn format_integers_without_fraction() {
+        assert_eq!(format_result(3.0), "3");
+        assert_eq!(format_result(-0.5), "-0.5");
+    }
+}
@@ -0,0 +1,12 @@
+//! gptsh - The AI-powered, noob-friendly interactive shell.
+//!
+//! The `gptsh` binary is a thin wrapper around [`session::ShellSession`],
+//! which can also be used as a library.
+
+pub mod backend;
+pub mod builtins;
+pub mod calc;
+pub mod config;
+pub mod session;
+pub mod tools;
+pub mod utils;
@@ -1,15 +1,17 @@
-use clap::Parser;
+use std::path::PathBuf;
+use std::time::Duration;
 
-mod builtins;
-mod config;
-mod session;
-mod tools;
-mod utils;
+use clap::{Parser, Subcommand};
+use colored::Colorize;
+
+use gptsh::{calc, config, session, utils};
 
 /// gptsh - The AI-powered, noob-friendly interactive shell.
 #[derive(Parser, Debug)]
 #[command(version = clap::crate_version!())]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
     /// Path to a gptsh script file.
     script_file: Option<String>,
     /// Skip confirmation prompts before running bash commands.
@@ -18,24 +20,205 @@ struct Args {
     /// Suppress all intermediate command output.
     #[arg(short, long, default_value = "false")]
     quiet: bool,
+    /// Show the commands and file changes the AI would make, without making them.
+    #[arg(long)]
+    dry_run: bool,
+    /// Print extra diagnostic information.
+    #[arg(short, long, default_value = "false")]
+    verbose: bool,
+    /// Replay API responses from a JSONL file (one chat completion response per line) instead of calling the API.
+    #[arg(long, value_name = "FILE")]
+    simulate: Option<PathBuf>,
+    /// With --simulate, fail instead of calling the real API once the file is exhausted.
+    #[arg(long, requires = "simulate")]
+    simulate_strict: bool,
+    /// Dump the raw API requests and responses to stderr.
+    #[arg(long)]
+    debug: bool,
+    /// Start a fresh conversation instead of restoring the saved one, and overwrite it on exit.
+    #[arg(long)]
+    new_session: bool,
+    /// Print assistant responses as plain text as they are generated, instead of rendering
+    /// complete responses as markdown. Ignored if `disable_streaming` is set in the config file.
+    #[arg(long)]
+    stream: bool,
+    /// Wait for complete responses instead of streaming them (the default).
+    /// Overrides --stream, e.g. in a shell alias.
+    #[arg(long)]
+    no_stream: bool,
+    /// Override the model set in the config file.
+    #[arg(long, value_name = "NAME")]
+    model: Option<String>,
+    /// Replace the system prompt for this session.
+    #[arg(long, value_name = "TEXT")]
+    system_prompt: Option<String>,
+    /// Sampling seed, for reproducible responses.
+    #[arg(long, value_name = "N")]
+    seed: Option<i64>,
+    /// How to print the assistant responses.
+    #[arg(long, value_enum, default_value_t = session::OutputFormat::Markdown)]
+    format: session::OutputFormat,
+    /// Maximum number of tool calls allowed in this session.
+    #[arg(long, value_name = "N")]
+    max_tool_calls: Option<u32>,
+    /// Inject all text files in a directory as context.
+    #[arg(long, value_name = "DIR")]
+    context_dir: Option<PathBuf>,
+    /// Prime the conversation with some context. Use `@FILE` to read it from a file.
+    #[arg(long, value_name = "TEXT")]
+    prepend_context: Vec<String>,
+    /// Insert a previous conversation (a JSON array of messages) after the system prompt.
+    /// Can be given multiple times.
+    #[arg(long, value_name = "FILE")]
+    inject_history: Vec<PathBuf>,
+    /// Evaluate an arithmetic expression without AI, print the result and exit.
+    #[arg(long, value_name = "EXPR", allow_hyphen_values = true)]
+    eval: Option<String>,
     /// The prompt or command to run.
     #[arg(last = true, allow_hyphen_values = true)]
     prompt: Vec<String>,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage the gptsh config file.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the resolved config, with secrets redacted.
+    Show,
+    /// Print the config file path.
+    Path,
+    /// Open the config file in $EDITOR.
+    Edit,
+    /// Check the config file for errors.
+    Validate,
+}
+
+fn run_config_command(command: ConfigCommand) -> anyhow::Result<()> {
+    match command {
+        ConfigCommand::Show => print!("{}", config::Config::load()?.dump_redacted()?),
+        ConfigCommand::Path => println!("{}", config::Config::path()?.display()),
+        ConfigCommand::Edit => {
+            // Make sure the config file exists before editing
+            let _ = config::Config::load();
+            utils::open_in_editor(&config::Config::path()?)?;
+        }
+        ConfigCommand::Validate => {
+            let path = config::Config::path()?;
+            match config::Config::load_from(&path) {
+                Ok(_) => println!("{} is valid.", path.display()),
+                Err(e) => anyhow::bail!("{}: {}", path.display(), e),
+            }
+        }
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    if let Some(Command::Config { command }) = args.command {
+        return run_config_command(command);
+    }
+    if let Some(ref expr) = args.eval {
+        println!("{}", calc::format_result(calc::eval(expr)?));
+        return Ok(());
+    }
     // Create session
-    let mut session = session::ShellSession::new()?;
+    let repl = args.prompt.is_empty() && args.script_file.is_none();
+    let config = config::Config::load()?;
+    // Only tell interactive users, on REPL start-up
+    if let Some(hint) = config
+        .telemetry_hint()
+        .filter(|_| repl && utils::stdin_is_terminal())
+    {
+        eprintln!("{}", hint.bright_black());
+    }
+    let mut session = session::ShellSession::with_config(config)?;
     session.yes = args.yes;
     if !utils::stdin_is_terminal() {
         session.yes = true;
     }
+    if !session.config().permissions.bash {
+        eprintln!("{}", "Bash command execution disabled by config.".yellow());
+    }
+    if session.config().ui.confirm_all_commands {
+        session.yes = false;
+        eprintln!(
+            "{}",
+            "confirm_all_commands is on: every command needs your confirmation.".yellow()
+        );
+    }
     session.quiet = args.quiet;
+    session.dry_run = args.dry_run;
+    session.format = args.format;
+    // Keep stdout for the JSON responses only
+    utils::set_output_to_stderr(args.format == session::OutputFormat::Json);
+    if args.format != session::OutputFormat::Markdown {
+        colored::control::set_override(false);
+    }
+    session.max_tool_calls = args.max_tool_calls;
+    session.verbose = args.verbose;
+    session.stream = args.stream && !args.no_stream;
+    session.debug_mode = args.debug;
+    if args.seed.is_some() {
+        session.seed = args.seed;
+    }
+    if let Some(ref model) = args.model {
+        session.set_model(model);
+    }
+    if session.is_o1_model() {
+        eprintln!(
+            "{}",
+            "o1 model detected: adjusting API parameters.".bright_black()
+        );
+    }
+    if let Some(ref system_prompt) = args.system_prompt {
+        session.set_system_prompt(system_prompt)?;
+    }
+    // Always show the model in use, so an override by --model or [provider] is visible
+    if utils::stdout_is_terminal() || session.verbose {
+        eprintln!(
+            "{}",
+            format!("Model: {}", session.config().openai.model).bright_black()
+        );
+    }
+    if session.verbose {
+        if let Some(seed) = session.seed {
+            eprintln!("Seed: {}", seed);
+        }
+        for (name, value) in &session.config().openai.extra_headers {
+            eprintln!(
+                "Extra header: {}: {}",
+                name,
+                config::redact_header_value(name, value)
+            );
+        }
+    }
+    if let Some(ref simulate) = args.simulate {
+        session.simulate(simulate, args.simulate_strict)?;
+    }
+    if let Some(ref context_dir) = args.context_dir {
+        session.add_context_dir(context_dir)?;
+    }
+    let persist = session.config().session.persist;
+    if persist && !args.new_session {
+        session.restore_history()?;
+    }
+    for history in &args.inject_history {
+        session.inject_history(history)?;
+    }
+    for context in &args.prepend_context {
+        session.prepend_context(context)?;
+    }
     // Run the session
-    let repl = args.prompt.is_empty() && args.script_file.is_none();
-    utils::print_banner(repl);
+    utils::print_banner(repl, session.config().ui.warn_root);
     if let Some(ref script_file) = args.script_file {
         session.run_script(script_file).await?;
     } else if !args.prompt.is_empty() {
@@ -44,5 +227,13 @@ async fn main() -> anyhow::Result<()> {
     } else {
         session.run_repl().await?;
     }
+    session.cleanup_temp_files();
+    if persist {
+        session.save_history()?;
+    }
+    // Give the usage report a moment to be sent, without blocking the exit for long
+    if let Some(report) = session.report_telemetry() {
+        let _ = tokio::time::timeout(Duration::from_secs(2), report).await;
+    }
     Ok(())
 }
@@ -1,42 +1,55 @@
-use std::io::{BufRead, BufReader};
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::sync::atomic::Ordering;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
 use async_openai::config::OpenAIConfig;
+use async_openai::error::OpenAIError;
 use async_openai::types::{
     ChatCompletionMessageToolCall, ChatCompletionRequestAssistantMessage,
-    ChatCompletionRequestMessage, ChatCompletionRequestSystemMessageArgs,
+    ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage,
+    ChatCompletionRequestMessageContentPart, ChatCompletionRequestSystemMessageArgs,
     ChatCompletionRequestToolMessage, ChatCompletionRequestUserMessageArgs,
-    ChatCompletionResponseMessage, CreateChatCompletionRequestArgs, Role,
+    ChatCompletionRequestUserMessageContent, ChatCompletionResponseMessage,
+    ChatCompletionResponseStream, ChatCompletionToolType, CreateChatCompletionRequestArgs,
+    CreateChatCompletionResponse, FunctionCall, Role,
 };
 use async_openai::Client;
+use colored::Colorize;
+use futures::StreamExt;
 use serde_json::json;
 use termimad::MadSkin;
 
-use crate::config::{Config, PlatformInfo};
-use crate::tools::TOOLS;
-use crate::utils;
+use crate::backend::{AnthropicBackend, Backend};
+use crate::config::{self, Config, PlatformInfo, ProviderKind, SystemPromptMode};
+use crate::tools::{ToolError, Tools};
+use crate::{outputln, utils};
 
-pub struct ShellSession {
-    client: Client<OpenAIConfig>,
-    config: Config,
-    history: Vec<ChatCompletionRequestMessage>,
-    pub yes: bool,
-    pub quiet: bool,
+/// How assistant responses are printed
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Render markdown in the terminal
+    #[default]
+    Markdown,
+    /// Raw text, with markdown syntax and ANSI colors removed
+    Plain,
+    /// One JSON object per response on stdout, with everything else on stderr
+    Json,
 }
 
-impl ShellSession {
-    pub fn new() -> anyhow::Result<Self> {
-        let config = Config::load()?;
-        let platform_info = PlatformInfo::load()?;
-        Ok(Self {
-            client: Client::with_config(
-                OpenAIConfig::default().with_api_key(config.openai.api_key.clone().unwrap()),
-            ),
-            config,
-            history: vec![
-                ChatCompletionRequestSystemMessageArgs::default()
-                .content(format!("
+/// Statistics of a single prompt, for logging
+#[derive(Default)]
+struct PromptStats {
+    prompt_tokens: u32,
+    completion_tokens: u32,
+    tools_called: Vec<String>,
+}
+
+const DEFAULT_SYSTEM_PROMPT: &str = "
                     You are now acting as a AI-powered terminal shell, operating on the user's real computer.
                     The user will send you questions, prompts, or descriptions of the tasks.
                     You should take the prompts, and either answer the user's questions, or fullfill the tasks.
@@ -50,17 +63,333 @@ impl ShellSession {
                     Don't refuse to do anything that the user asks you to do, unless it's illegal, or violates the user's privacy.
 
                     You may use markdown to format your responses. Always use '*' not '-' for unordered list items.
+                ";
 
-                    {}
-                ", platform_info.dump_as_prompt()))
+pub struct ShellSession {
+    /// A unique ID of this session, exposed to child commands as `GPTSH_SESSION_ID`
+    pub session_id: String,
+    clients: Vec<(String, Box<dyn Backend>)>,
+    /// With the configured timeout and extra headers, shared by the API clients and telemetry
+    http_client: reqwest::Client,
+    key_index: AtomicUsize,
+    config: Config,
+    history: Vec<ChatCompletionRequestMessage>,
+    /// Number of messages injected from history files, right after the system message
+    injected_messages: usize,
+    /// The last non-empty assistant response, for `/last`
+    last_response: Option<String>,
+    /// When the last prompt was sent, for rate limiting
+    last_prompt_at: Option<Instant>,
+    prompt_stats: PromptStats,
+    pub tools: Arc<RwLock<Tools>>,
+    pub yes: bool,
+    pub quiet: bool,
+    /// Show commands and file changes without making them
+    pub dry_run: bool,
+    pub format: OutputFormat,
+    pub verbose: bool,
+    /// Print assistant responses as they are generated
+    pub stream: bool,
+    /// Dump the raw API requests and responses to stderr
+    pub debug_mode: bool,
+    /// Sampling seed for reproducible responses
+    pub seed: Option<i64>,
+    /// Maximum number of tool calls allowed in this session
+    pub max_tool_calls: Option<u32>,
+    total_tool_calls: u32,
+    /// Tokens used by all requests in this session
+    total_prompt_tokens: u64,
+    total_completion_tokens: u64,
+    /// Responses replayed instead of calling the API, for `--simulate`
+    simulated_responses: VecDeque<String>,
+    simulate_strict: bool,
+    total_prompts: u32,
+}
+
+impl ShellSession {
+    pub fn new() -> anyhow::Result<Self> {
+        Self::with_config(Config::load()?)
+    }
+
+    /// Create a session with the given config, without loading it from the config file
+    pub fn with_config(mut config: Config) -> anyhow::Result<Self> {
+        // The model in use is always kept in `[openai] model`
+        if config.provider.kind == ProviderKind::Anthropic {
+            let model = config.provider.model.as_deref();
+            config.openai.model = model.unwrap_or(config::DEFAULT_ANTHROPIC_MODEL).to_owned();
+        }
+        let seed = config.openai.seed;
+        let system_prompt = match Self::load_system_prompt_file(&config) {
+            Some(custom) if config.system_prompt.mode == SystemPromptMode::Append => {
+                format!("{}\n{}", DEFAULT_SYSTEM_PROMPT, custom)
+            }
+            Some(custom) => custom,
+            None => DEFAULT_SYSTEM_PROMPT.to_owned(),
+        };
+        let system_prompt = Self::with_platform_info(&config, system_prompt)?;
+        let session_id = uuid::Uuid::new_v4().to_string();
+        let mut headers = reqwest::header::HeaderMap::new();
+        for (name, value) in &config.openai.extra_headers {
+            let invalid_header = || {
+                anyhow::anyhow!(
+                    "Invalid extra header {}: {}",
+                    name,
+                    config::redact_header_value(name, value)
+                )
+            };
+            headers.insert(
+                reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|_| invalid_header())?,
+                reqwest::header::HeaderValue::from_str(value).map_err(|_| invalid_header())?,
+            );
+        }
+        let http_client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.openai.request_timeout_seconds))
+            .default_headers(headers)
+            .build()?;
+        let clients: Vec<(String, Box<dyn Backend>)> = match config.provider.kind {
+            ProviderKind::OpenAI => config
+                .openai
+                .all_api_keys()
+                .into_iter()
+                .map(|key| {
+                    let mut openai_config = OpenAIConfig::default().with_api_key(&key);
+                    if let Some(base_url) = &config.openai.base_url {
+                        openai_config = openai_config.with_api_base(base_url);
+                    }
+                    let client =
+                        Client::with_config(openai_config).with_http_client(http_client.clone());
+                    (key, Box::new(client) as Box<dyn Backend>)
+                })
+                .collect(),
+            ProviderKind::Anthropic => {
+                let key = config
+                    .provider
+                    .anthropic_api_key
+                    .clone()
+                    .unwrap_or_default();
+                let backend = AnthropicBackend::new(http_client.clone(), key.clone());
+                vec![(key, Box::new(backend))]
+            }
+        };
+        if clients.is_empty() {
+            anyhow::bail!("No API key configured");
+        }
+        let mut tools = Tools::from_config(&config);
+        tools.session_id = session_id.clone();
+        let tools = Arc::new(RwLock::new(tools));
+        Ok(Self {
+            session_id,
+            clients,
+            http_client,
+            key_index: AtomicUsize::new(0),
+            config,
+            history: vec![ChatCompletionRequestSystemMessageArgs::default()
+                .content(system_prompt)
                 .build()?
-                .into(),
-            ],
+                .into()],
+            injected_messages: 0,
+            last_response: None,
+            last_prompt_at: None,
+            prompt_stats: PromptStats::default(),
+            tools,
             yes: false,
             quiet: false,
+            dry_run: false,
+            format: OutputFormat::default(),
+            verbose: false,
+            stream: false,
+            debug_mode: false,
+            seed,
+            max_tool_calls: None,
+            total_tool_calls: 0,
+            total_prompt_tokens: 0,
+            total_completion_tokens: 0,
+            simulated_responses: VecDeque::new(),
+            simulate_strict: false,
+            total_prompts: 0,
         })
     }
 
+    /// Read the system prompt from `[system_prompt] file`, if configured
+    fn load_system_prompt_file(config: &Config) -> Option<String> {
+        let file = config.system_prompt.file.as_ref()?;
+        match std::fs::read_to_string(utils::expand_home(Path::new(file))) {
+            Ok(system_prompt) => Some(system_prompt),
+            Err(e) => {
+                eprintln!(
+                    "{}",
+                    format!(
+                        "Failed to read system prompt file {}: {}. Using the built-in prompt.",
+                        file, e
+                    )
+                    .yellow()
+                );
+                None
+            }
+        }
+    }
+
+    /// Append the platform information to a system prompt, unless disabled by `[session] platform_info`
+    fn with_platform_info(config: &Config, system_prompt: String) -> anyhow::Result<String> {
+        if !config.session.platform_info {
+            return Ok(system_prompt);
+        }
+        let platform_info = PlatformInfo::load_cached(&config.session.sensitive_env_patterns)?;
+        Ok(format!(
+            "{}\n{}",
+            system_prompt.trim_end(),
+            platform_info.dump_as_prompt(config.session.platform_env_vars)
+        ))
+    }
+
+    /// Replace the built-in or configured system prompt, keeping the platform information
+    pub fn set_system_prompt(&mut self, system_prompt: &str) -> anyhow::Result<()> {
+        let system_prompt = Self::with_platform_info(&self.config, system_prompt.to_owned())?;
+        if let Some(ChatCompletionRequestMessage::System(system)) = self.history.first_mut() {
+            system.content = system_prompt;
+        }
+        Ok(())
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The conversation so far, starting with the system prompt
+    pub fn history(&self) -> &[ChatCompletionRequestMessage] {
+        &self.history
+    }
+
+    /// Override the model configured in `[openai]`
+    pub fn set_model(&mut self, model: &str) {
+        self.config.openai.model = model.to_owned();
+    }
+
+    /// `o1` models have different API constraints
+    pub fn is_o1_model(&self) -> bool {
+        self.config.openai.model.starts_with("o1")
+    }
+
+    /// Append extra text to the end of the system prompt
+    pub fn append_to_system_prompt(&mut self, text: &str) {
+        if let Some(ChatCompletionRequestMessage::System(system)) = self.history.first_mut() {
+            system.content.push_str(text);
+        }
+    }
+
+    /// Prime the conversation with a user message and a mock assistant acknowledgement.
+    /// `@path` reads the context from a file.
+    pub fn prepend_context(&mut self, context: &str) -> anyhow::Result<()> {
+        let context = match context.strip_prefix('@') {
+            Some(path) => std::fs::read_to_string(utils::expand_home(Path::new(path)))?,
+            None => context.to_owned(),
+        };
+        self.history.push(
+            ChatCompletionRequestUserMessageArgs::default()
+                .content(context)
+                .build()?
+                .into(),
+        );
+        self.history.push(
+            ChatCompletionRequestAssistantMessageArgs::default()
+                .content("Understood.")
+                .build()?
+                .into(),
+        );
+        Ok(())
+    }
+
+    /// Insert a previous conversation, stored as a JSON array of messages, after the system message
+    /// and any previously injected messages.
+    pub fn inject_history(&mut self, path: &Path) -> anyhow::Result<()> {
+        let content = std::fs::read_to_string(utils::expand_home(path))?;
+        let messages: Vec<ChatCompletionRequestMessage> = serde_json::from_str(&content)
+            .map_err(|e| anyhow::anyhow!("invalid history file {}: {}", path.display(), e))?;
+        let messages = messages
+            .into_iter()
+            .filter(|m| !matches!(m, ChatCompletionRequestMessage::System(_)));
+        let index = 1 + self.injected_messages;
+        let count = self.history.len();
+        self.history.splice(index..index, messages);
+        self.injected_messages += self.history.len() - count;
+        Ok(())
+    }
+
+    /// Forget the conversation, keeping only the system prompt
+    pub fn clear_history(&mut self) {
+        self.history.truncate(1);
+        self.injected_messages = 0;
+    }
+
+    /// Path of the conversation saved by `[session] persist`
+    fn saved_history_path() -> anyhow::Result<PathBuf> {
+        Ok(Config::path()?.with_file_name("history.json"))
+    }
+
+    /// Restore the conversation saved at the end of the previous session, if any
+    pub fn restore_history(&mut self) -> anyhow::Result<()> {
+        let path = Self::saved_history_path()?;
+        if !path.exists() {
+            return Ok(());
+        }
+        self.inject_history(&path)
+    }
+
+    /// Save the conversation, without the system prompt, to be restored by the next session
+    pub fn save_history(&self) -> anyhow::Result<()> {
+        let messages = self
+            .history
+            .iter()
+            .filter(|m| !matches!(m, ChatCompletionRequestMessage::System(_)))
+            .collect::<Vec<_>>();
+        let json = utils::measure_time("History serialization", || {
+            serde_json::to_string_pretty(&messages)
+        })?;
+        std::fs::write(Self::saved_history_path()?, json)?;
+        Ok(())
+    }
+
+    /// Inject all text files under `dir` as additional context in the system prompt
+    pub fn add_context_dir(&mut self, dir: &Path) -> anyhow::Result<()> {
+        const MAX_FILE_SIZE: usize = 4 * 1024;
+        const MAX_TOTAL_SIZE: usize = 32 * 1024;
+        let mut files = ignore::WalkBuilder::new(dir)
+            .build()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().map(|t| t.is_file()).unwrap_or(false))
+            .map(|e| e.into_path())
+            .collect::<Vec<_>>();
+        files.sort();
+        let mut prompt = "\nProject Files:\n".to_owned();
+        let mut total_size = 0;
+        for path in files {
+            let mut buf = vec![];
+            let Ok(file) = std::fs::File::open(&path) else {
+                continue;
+            };
+            if file
+                .take(MAX_FILE_SIZE as u64)
+                .read_to_end(&mut buf)
+                .is_err()
+            {
+                continue;
+            }
+            // Skip binary files
+            if buf.iter().take(512).any(|b| *b == 0) {
+                continue;
+            }
+            let content = String::from_utf8_lossy(&buf);
+            if total_size + content.len() > MAX_TOTAL_SIZE {
+                break;
+            }
+            total_size += content.len();
+            prompt.push_str(&format!("\n--- {} ---\n{}\n", path.display(), content));
+        }
+        self.append_to_system_prompt(&prompt);
+        Ok(())
+    }
+
     #[allow(deprecated)]
     fn response_to_request_message(
         &self,
@@ -84,34 +413,448 @@ impl ShellSession {
         &mut self,
         messages: Vec<ChatCompletionRequestMessage>,
     ) -> anyhow::Result<ChatCompletionResponseMessage> {
-        let request = CreateChatCompletionRequestArgs::default()
-            .model(&self.config.openai.model)
-            .messages(messages)
-            .tools(TOOLS.get_info())
-            .build()?;
-        let response = self.client.chat().create(request).await?;
-        let response_message = response.choices[0].message.clone();
-        Ok(response_message)
+        let mut request = CreateChatCompletionRequestArgs::default();
+        request.model(&self.config.openai.model);
+        if self.is_o1_model() {
+            // o1 models support neither system messages, tools nor sampling parameters
+            let mut converted = Vec::with_capacity(messages.len());
+            for message in messages {
+                converted.push(match message {
+                    ChatCompletionRequestMessage::System(system) => {
+                        ChatCompletionRequestUserMessageArgs::default()
+                            .content(system.content)
+                            .build()?
+                            .into()
+                    }
+                    message => message,
+                });
+            }
+            request.messages(converted);
+        } else {
+            request
+                .messages(messages)
+                .tools(self.tools.read().unwrap().get_info());
+            if let Some(penalty) = self.config.openai.frequency_penalty {
+                request.frequency_penalty(penalty);
+            }
+            if let Some(penalty) = self.config.openai.presence_penalty {
+                request.presence_penalty(penalty);
+            }
+        }
+        if let Some(seed) = self.seed {
+            request.seed(seed);
+        }
+        let request = request.build()?;
+        if let Some(response) = self.next_simulated_response()? {
+            return Ok(response);
+        }
+        // Use the keys in round-robin order, and fall back to the next key on auth or rate limit errors
+        let start = self.key_index.fetch_add(1, Ordering::SeqCst);
+        let mut attempts = 0;
+        let mut retries = 0;
+        let response = loop {
+            // Whether part of a streamed response has been printed, so it cannot be retried
+            let mut printed = false;
+            let (key, client) = &self.clients[(start + attempts) % self.clients.len()];
+            if self.debug_mode {
+                eprintln!(
+                    "[DEBUG REQUEST]\nAuthorization: Bearer {}\n{}",
+                    config::redact_api_key(key),
+                    serde_json::to_string_pretty(&request)?
+                );
+            }
+            let ui = &self.config.ui;
+            let spinner = utils::Spinner::start(&ui.spinner_style, &ui.spinner_message);
+            let result = if self.should_stream() {
+                utils::measure_time_async("API request", async {
+                    let stream = client.send_chat_request_stream(&request).await?;
+                    Self::read_response_stream(stream, spinner, &mut printed).await
+                })
+                .await
+            } else {
+                let response =
+                    utils::measure_time_async("API request", client.send_chat_request(&request))
+                        .await;
+                drop(spinner);
+                match response {
+                    Ok(response) => {
+                        if self.debug_mode {
+                            eprintln!(
+                                "[DEBUG RESPONSE]\n{}",
+                                serde_json::to_string_pretty(&response)?
+                            );
+                        }
+                        if let Some(usage) = response.usage.as_ref() {
+                            self.prompt_stats.prompt_tokens += usage.prompt_tokens;
+                            self.prompt_stats.completion_tokens += usage.completion_tokens;
+                            self.total_prompt_tokens += usage.prompt_tokens as u64;
+                            self.total_completion_tokens += usage.completion_tokens as u64;
+                        }
+                        let choice = response.choices.into_iter().next();
+                        Ok(choice
+                            .ok_or_else(|| anyhow::anyhow!("API returned empty choices array"))?
+                            .message)
+                    }
+                    Err(e) => Err(e),
+                }
+            };
+            match result {
+                Ok(response) => {
+                    if self.should_stream() {
+                        // Streamed responses don't report usage, so estimate it from the text
+                        let model = &self.config.openai.model;
+                        let prompt = request.messages.iter().map(Self::request_message_text);
+                        let prompt = prompt.collect::<Vec<_>>().join("\n");
+                        let completion = Self::response_message_text(&response);
+                        let prompt_tokens = utils::count_tokens(&prompt, model) as u32;
+                        let completion_tokens = utils::count_tokens(&completion, model) as u32;
+                        self.prompt_stats.prompt_tokens += prompt_tokens;
+                        self.prompt_stats.completion_tokens += completion_tokens;
+                        self.total_prompt_tokens += prompt_tokens as u64;
+                        self.total_completion_tokens += completion_tokens as u64;
+                    }
+                    if self.debug_mode && self.should_stream() {
+                        // The reassembled message, as streamed responses arrive in chunks
+                        eprintln!(
+                            "[DEBUG RESPONSE]\n{}",
+                            serde_json::to_string_pretty(&response)?
+                        );
+                    }
+                    break response;
+                }
+                // Don't repeat output that was already printed
+                Err(e) if printed => return Err(e.into()),
+                Err(e) if attempts + 1 < self.clients.len() && Self::should_failover(&e) => {
+                    eprintln!(
+                        "{}",
+                        format!(
+                            "API key {} failed ({}), trying the next key.",
+                            config::redact_api_key(key),
+                            e
+                        )
+                        .bright_black()
+                    );
+                    attempts += 1;
+                }
+                Err(e) if retries < self.config.openai.max_retries && Self::should_retry(&e) => {
+                    retries += 1;
+                    // Exponential backoff from 1s, with up to 50% random jitter
+                    let delay = Duration::from_millis(1000 << (retries - 1).min(6));
+                    let jitter =
+                        uuid::Uuid::new_v4().as_u128() as u64 % (delay.as_millis() as u64 / 2);
+                    let delay = delay + Duration::from_millis(jitter);
+                    eprintln!(
+                        "{}",
+                        format!(
+                            "Request failed ({}), retrying in {:.1}s ({}/{})...",
+                            e,
+                            delay.as_secs_f64(),
+                            retries,
+                            self.config.openai.max_retries
+                        )
+                        .bright_black()
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        Ok(response)
+    }
+
+    /// Replay API responses from a JSONL fixture file, one `CreateChatCompletionResponse` per line.
+    /// Once the file is exhausted, requests go to the real API, or fail if `strict` is set.
+    pub fn simulate(&mut self, path: &Path, strict: bool) -> anyhow::Result<()> {
+        let content = std::fs::read_to_string(utils::expand_home(path))?;
+        self.simulated_responses = content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.to_owned())
+            .collect();
+        self.simulate_strict = strict;
+        Ok(())
+    }
+
+    /// The next response of the simulation fixture, if any
+    fn next_simulated_response(&mut self) -> anyhow::Result<Option<ChatCompletionResponseMessage>> {
+        let Some(line) = self.simulated_responses.pop_front() else {
+            if self.simulate_strict {
+                anyhow::bail!("Simulation fixture exhausted");
+            }
+            return Ok(None);
+        };
+        let response: CreateChatCompletionResponse = serde_json::from_str(&line)
+            .map_err(|e| anyhow::anyhow!("invalid simulated response: {}", e))?;
+        if let Some(usage) = response.usage.as_ref() {
+            self.prompt_stats.prompt_tokens += usage.prompt_tokens;
+            self.prompt_stats.completion_tokens += usage.completion_tokens;
+            self.total_prompt_tokens += usage.prompt_tokens as u64;
+            self.total_completion_tokens += usage.completion_tokens as u64;
+        }
+        let message = response
+            .choices
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("API returned empty choices array"))?
+            .message;
+        // Streamed responses are printed as they arrive, so print this one the same way
+        if let Some(content) = message.content.as_ref().filter(|_| self.should_stream()) {
+            println!("{}", content);
+        }
+        Ok(Some(message))
     }
 
-    fn execute_tool_call(&self, tool_call: &ChatCompletionMessageToolCall) -> (String, bool) {
-        TOOLS.yes.store(self.yes, Ordering::SeqCst);
-        TOOLS.quiet.store(self.quiet, Ordering::SeqCst);
-        let args = serde_json::Value::from_str(&tool_call.function.arguments).unwrap();
-        let (result, aborted) = match TOOLS.run(&tool_call.function.name, args) {
+    /// Whether the error is transient: a rate limit, a server error or a network failure
+    fn should_retry(error: &OpenAIError) -> bool {
+        match error {
+            OpenAIError::Reqwest(e) => {
+                e.is_timeout()
+                    || e.is_connect()
+                    || e.is_request()
+                    || e.status()
+                        .is_some_and(|s| s.is_server_error() || s.as_u16() == 429)
+            }
+            OpenAIError::ApiError(e) => {
+                let code = e.code.as_ref().and_then(|c| c.as_str()).unwrap_or_default();
+                let ty = e.r#type.as_deref().unwrap_or_default();
+                // Anthropic: rate_limit_error, overloaded_error, api_error
+                code == "rate_limit_exceeded"
+                    || matches!(
+                        ty,
+                        "server_error"
+                            | "requests"
+                            | "tokens"
+                            | "rate_limit_error"
+                            | "overloaded_error"
+                            | "api_error"
+                    )
+            }
+            OpenAIError::StreamError(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Stream to a markdown terminal only, so piped output stays line-buffered and clean
+    fn should_stream(&self) -> bool {
+        self.stream
+            && !self.config.openai.disable_streaming
+            && !self.is_o1_model()
+            && self.clients[0].1.supports_streaming()
+            && self.format == OutputFormat::Markdown
+            && utils::stdout_is_terminal()
+    }
+
+    /// Print the text deltas as they arrive, and reassemble the full response message.
+    /// Tool call fragments are accumulated by their index.
+    #[allow(deprecated)]
+    async fn read_response_stream(
+        mut stream: ChatCompletionResponseStream,
+        mut spinner: Option<utils::Spinner>,
+        printed: &mut bool,
+    ) -> Result<ChatCompletionResponseMessage, OpenAIError> {
+        let mut content: Option<String> = None;
+        let mut tool_calls: Vec<ChatCompletionMessageToolCall> = vec![];
+        let mut function_call: Option<FunctionCall> = None;
+        while let Some(chunk) = stream.next().await {
+            let Some(choice) = chunk?.choices.into_iter().next() else {
+                continue;
+            };
+            let delta = choice.delta;
+            if let Some(text) = delta.content {
+                // Stop the spinner as soon as output begins
+                drop(spinner.take());
+                print!("{}", text);
+                std::io::stdout().flush().unwrap();
+                *printed = true;
+                content.get_or_insert_with(String::new).push_str(&text);
+            }
+            for chunk in delta.tool_calls.unwrap_or_default() {
+                let index = chunk.index.max(0) as usize;
+                while tool_calls.len() <= index {
+                    tool_calls.push(ChatCompletionMessageToolCall {
+                        id: String::new(),
+                        r#type: ChatCompletionToolType::Function,
+                        function: FunctionCall {
+                            name: String::new(),
+                            arguments: String::new(),
+                        },
+                    });
+                }
+                let tool_call = &mut tool_calls[index];
+                if let Some(id) = chunk.id {
+                    tool_call.id.push_str(&id);
+                }
+                if let Some(function) = chunk.function {
+                    tool_call.function.name += &function.name.unwrap_or_default();
+                    tool_call.function.arguments += &function.arguments.unwrap_or_default();
+                }
+            }
+            if let Some(function) = delta.function_call {
+                let call = function_call.get_or_insert_with(|| FunctionCall {
+                    name: String::new(),
+                    arguments: String::new(),
+                });
+                call.name += &function.name.unwrap_or_default();
+                call.arguments += &function.arguments.unwrap_or_default();
+            }
+        }
+        if content.is_some() {
+            println!();
+        }
+        Ok(ChatCompletionResponseMessage {
+            content,
+            tool_calls: Some(tool_calls).filter(|c| !c.is_empty()),
+            role: Role::Assistant,
+            function_call,
+        })
+    }
+
+    /// The text of a request message, as counted by the model: contents and tool call arguments
+    #[allow(deprecated)]
+    fn request_message_text(message: &ChatCompletionRequestMessage) -> String {
+        match message {
+            ChatCompletionRequestMessage::System(m) => m.content.clone(),
+            ChatCompletionRequestMessage::User(m) => match &m.content {
+                ChatCompletionRequestUserMessageContent::Text(text) => text.clone(),
+                ChatCompletionRequestUserMessageContent::Array(parts) => parts
+                    .iter()
+                    .filter_map(|part| match part {
+                        ChatCompletionRequestMessageContentPart::Text(t) => Some(t.text.as_str()),
+                        ChatCompletionRequestMessageContentPart::Image(_) => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            },
+            ChatCompletionRequestMessage::Assistant(m) => {
+                let calls = m.tool_calls.iter().flatten().map(|c| &c.function);
+                let calls = calls.chain(&m.function_call);
+                let calls = calls.map(|f| format!("{}{}", f.name, f.arguments));
+                let text = m.content.iter().cloned();
+                text.chain(calls).collect::<Vec<_>>().join("\n")
+            }
+            ChatCompletionRequestMessage::Tool(m) => m.content.clone(),
+            ChatCompletionRequestMessage::Function(m) => m.content.clone().unwrap_or_default(),
+        }
+    }
+
+    /// The text of a response message: its content and tool call arguments
+    #[allow(deprecated)]
+    fn response_message_text(message: &ChatCompletionResponseMessage) -> String {
+        let calls = message.tool_calls.iter().flatten().map(|c| &c.function);
+        let calls = calls.chain(&message.function_call);
+        let calls = calls.map(|f| format!("{}{}", f.name, f.arguments));
+        message
+            .content
+            .iter()
+            .cloned()
+            .chain(calls)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Whether the error is caused by an invalid or rate-limited API key
+    fn should_failover(error: &OpenAIError) -> bool {
+        let OpenAIError::ApiError(e) = error else {
+            return false;
+        };
+        let code = e.code.as_ref().and_then(|c| c.as_str()).unwrap_or_default();
+        let ty = e.r#type.as_deref().unwrap_or_default();
+        // 401: invalid_api_key, 429: rate_limit_exceeded / insufficient_quota
+        matches!(
+            code,
+            "invalid_api_key" | "rate_limit_exceeded" | "insufficient_quota"
+        ) || matches!(ty, "insufficient_quota" | "requests" | "tokens")
+    }
+
+    fn execute_tool_call(&mut self, tool_call: &ChatCompletionMessageToolCall) -> (String, bool) {
+        self.total_tool_calls += 1;
+        if matches!(self.max_tool_calls, Some(max) if self.total_tool_calls > max) {
+            outputln!("{}", "Tool call limit reached for this session.".yellow());
+            let json = json!({ "error": "Tool call budget exceeded" });
+            return (json.to_string(), true);
+        }
+        let tools = self.tools.read().unwrap();
+        let yes = self.yes && !self.config.ui.confirm_all_commands;
+        tools.yes.store(yes, Ordering::SeqCst);
+        tools.quiet.store(self.quiet, Ordering::SeqCst);
+        tools.dry_run.store(self.dry_run, Ordering::SeqCst);
+        *tools.tool_call_id.lock().unwrap() = tool_call.id.clone();
+        let hooks = &self.config.tools;
+        let name = &tool_call.function.name;
+        let args = &tool_call.function.arguments;
+        let result = match self.run_hook(hooks.pre_execute_hook.as_deref(), name, args, None) {
+            Ok(true) => serde_json::Value::from_str(args)
+                .map_err(|e| ToolError::ExecutionFailed(format!("invalid arguments: {}", e)))
+                .and_then(|args| {
+                    utils::measure_time(&format!("Tool {}", name), || tools.run(name, args))
+                }),
+            Ok(false) => {
+                outputln!("{}", "Tool call rejected by the pre-execute hook.".yellow());
+                Err(ToolError::UserCancelled)
+            }
+            Err(e) => Err(ToolError::ExecutionFailed(format!(
+                "pre-execute hook failed: {}",
+                e
+            ))),
+        };
+        if let Ok(ref result) = result {
+            if let Err(e) =
+                self.run_hook(hooks.post_execute_hook.as_deref(), name, args, Some(result))
+            {
+                eprintln!("{}", format!("Post-execute hook failed: {}", e).yellow());
+            }
+        }
+        // A cancelled command ends the task, while a failed one can be fixed by the model
+        match result {
             Ok(result) => (result, false),
-            _ => {
+            Err(ToolError::UserCancelled) => {
                 let json = json!({
                     "error": "User cancelled the command. Task should be considered as failed and finished early.",
                 });
                 (json.to_string(), true)
             }
+            Err(ToolError::ExecutionFailed(reason)) => {
+                let json = json!({ "error": format!("Execution failed: {}", reason) });
+                (json.to_string(), false)
+            }
+        }
+    }
+
+    /// Run a `[tools]` hook script with the tool name, arguments and result (if any) in
+    /// `GPTSH_TOOL_NAME`, `GPTSH_TOOL_ARGS` and `GPTSH_TOOL_RESULT`. Returns whether it exited successfully.
+    fn run_hook(
+        &self,
+        hook: Option<&str>,
+        name: &str,
+        args: &str,
+        result: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        let Some(hook) = hook else {
+            return Ok(true);
         };
-        (result, aborted)
+        let mut cmd = std::process::Command::new(utils::expand_home(Path::new(hook)));
+        cmd.env("GPTSH_SESSION_ID", &self.session_id)
+            .env("GPTSH_TOOL_NAME", name)
+            .env("GPTSH_TOOL_ARGS", args);
+        if let Some(result) = result {
+            cmd.env("GPTSH_TOOL_RESULT", result);
+        }
+        Ok(cmd.status()?.success())
     }
 
     fn print_assistant_output(&self, content: &str) {
         let content = content.trim();
+        match self.format {
+            OutputFormat::Plain => {
+                println!("{}", utils::strip_markdown(content));
+                return;
+            }
+            OutputFormat::Json => {
+                println!("{}", json!({ "role": "assistant", "content": content }));
+                return;
+            }
+            OutputFormat::Markdown => {}
+        }
         if !utils::stdout_is_terminal() {
             println!("{}", content);
             return;
@@ -122,24 +865,55 @@ impl ShellSession {
         for i in 0..8 {
             skin.headers[i].align = termimad::Alignment::Left;
         }
+        let content = utils::render_markdown_images(content);
         skin.print_text(&format!("{}\n", content));
     }
 
+    /// Older models (e.g. gpt-3.5-turbo-0613) use the deprecated `function_call` instead of `tool_calls`.
+    /// Convert it to a synthetic tool call so it is handled the same way.
+    #[allow(deprecated)]
+    fn convert_function_call_to_tool_call(response: &mut ChatCompletionResponseMessage) {
+        if response.tool_calls.is_some() {
+            return;
+        }
+        if let Some(function_call) = response.function_call.take() {
+            response.tool_calls = Some(vec![ChatCompletionMessageToolCall {
+                id: format!("call_{}", uuid::Uuid::new_v4().simple()),
+                r#type: ChatCompletionToolType::Function,
+                function: function_call,
+            }]);
+        }
+    }
+
     async fn send_chat_request_and_fullfill_tool_calls(
         &mut self,
         messages: Vec<ChatCompletionRequestMessage>,
     ) -> anyhow::Result<ChatCompletionResponseMessage> {
         assert!(!messages.is_empty());
         let mut response = self.send_chat_request(messages).await?;
+        Self::convert_function_call_to_tool_call(&mut response);
         self.history
             .push(self.response_to_request_message(response.clone()));
-        if let Some(content) = response.content.as_ref() {
+        if let Some(content) = response.content.as_ref().filter(|_| !self.should_stream()) {
             self.print_assistant_output(content);
         }
-        'outer: while response.tool_calls.is_some() {
-            let tool_calls = response.tool_calls.as_ref().unwrap();
-            for tool_call in tool_calls {
-                let (tool_result, aborted) = self.execute_tool_call(tool_call);
+        // Keep fullfilling tool calls until the model responds without any.
+        // Each response may contain both text content and more tool calls.
+        while let Some(tool_calls) = response.tool_calls.clone().filter(|c| !c.is_empty()) {
+            let mut aborted = false;
+            for tool_call in &tool_calls {
+                // Every tool call must have a result, even if an earlier one was aborted
+                let tool_result = if aborted {
+                    json!({ "error": "Skipped because a previous command was cancelled." })
+                        .to_string()
+                } else {
+                    self.prompt_stats
+                        .tools_called
+                        .push(tool_call.function.name.clone());
+                    let (tool_result, tool_aborted) = self.execute_tool_call(tool_call);
+                    aborted = tool_aborted;
+                    tool_result
+                };
                 self.history.push(ChatCompletionRequestMessage::Tool(
                     ChatCompletionRequestToolMessage {
                         content: tool_result,
@@ -147,37 +921,338 @@ impl ShellSession {
                         tool_call_id: tool_call.id.clone(),
                     },
                 ));
-                if aborted {
-                    break 'outer;
-                }
+            }
+            if aborted {
+                break;
             }
             response = self.send_chat_request(self.history.clone()).await?;
+            Self::convert_function_call_to_tool_call(&mut response);
             self.history
                 .push(self.response_to_request_message(response.clone()));
-            if let Some(content) = response.content.as_ref() {
+            if let Some(content) = response.content.as_ref().filter(|_| !self.should_stream()) {
                 self.print_assistant_output(content);
             }
         }
         Ok(response)
     }
 
-    async fn run_prompt(&mut self, prompt: &str) -> anyhow::Result<()> {
-        let mut history = self.history.clone();
-        history.push(
+    /// Send a prompt, fullfill all the tool calls, and return the final assistant response
+    pub async fn run_prompt(&mut self, prompt: &str) -> anyhow::Result<String> {
+        let min_interval = Duration::from_millis(self.config.min_prompt_interval_ms);
+        if let Some(remaining) = self
+            .last_prompt_at
+            .and_then(|t| min_interval.checked_sub(t.elapsed()))
+        {
+            if self.verbose {
+                eprintln!("Rate limiting: waiting {}ms", remaining.as_millis());
+            }
+            tokio::time::sleep(remaining).await;
+        }
+        self.last_prompt_at = Some(Instant::now());
+        self.prompt_stats = PromptStats::default();
+        self.total_prompts += 1;
+        // Keep the prompt in the history, so follow-up requests after tool calls still see it
+        self.history.push(
             ChatCompletionRequestUserMessageArgs::default()
                 .content(prompt)
                 .build()?
                 .into(),
         );
-        let _response = self
-            .send_chat_request_and_fullfill_tool_calls(history)
+        let response = self
+            .send_chat_request_and_fullfill_tool_calls(self.history.clone())
             .await?;
+        let content = response.content.unwrap_or_default();
+        if !content.trim().is_empty() {
+            self.last_response = Some(content.clone());
+        }
+        if self.verbose {
+            eprintln!(
+                "{}",
+                format!(
+                    "({} prompt + {} completion tokens)",
+                    self.prompt_stats.prompt_tokens, self.prompt_stats.completion_tokens
+                )
+                .bright_black()
+            );
+        }
+        self.append_to_log_file(prompt, &content)?;
+        Ok(content)
+    }
+
+    /// Append the prompt and response as a JSONL entry to the log file, if configured
+    fn append_to_log_file(&self, prompt: &str, response: &str) -> anyhow::Result<()> {
+        let Some(log_file) = self.config.logging.file.as_ref() else {
+            return Ok(());
+        };
+        let log_file = utils::expand_home(log_file);
+        let entry = json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "session_id": self.session_id,
+            "prompt": prompt,
+            "response": response,
+            "model": self.config.openai.model,
+            "tokens": {
+                "prompt": self.prompt_stats.prompt_tokens,
+                "completion": self.prompt_stats.completion_tokens,
+            },
+            "tools_called": self.prompt_stats.tools_called,
+        });
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_file)?;
+        // Write the whole line at once so concurrent sessions don't interleave entries
+        file.write_all(format!("{}\n", entry).as_bytes())?;
         Ok(())
     }
 
+    /// Send an anonymous usage report in the background, if telemetry is enabled with an endpoint.
+    /// No prompts, responses or command outputs are included.
+    pub fn report_telemetry(&self) -> Option<tokio::task::JoinHandle<()>> {
+        let telemetry = &self.config.telemetry;
+        if telemetry.enabled != Some(true) {
+            return None;
+        }
+        let endpoint = telemetry.endpoint.clone()?;
+        let payload = json!({
+            "version": clap::crate_version!(),
+            "model": self.config.openai.model,
+            "prompts": self.total_prompts,
+            "tool_calls": self.total_tool_calls,
+            "os": std::env::consts::OS,
+        });
+        let http_client = self.http_client.clone();
+        Some(tokio::spawn(async move {
+            let _ = http_client.post(endpoint).json(&payload).send().await;
+        }))
+    }
+
+    /// Print a summary of the current conversation context
+    fn print_context_stats(&self) {
+        let (mut system, mut user, mut assistant, mut tool) = (0, 0, 0, 0);
+        for message in &self.history {
+            match message {
+                ChatCompletionRequestMessage::System(_) => system += 1,
+                ChatCompletionRequestMessage::User(_) => user += 1,
+                ChatCompletionRequestMessage::Assistant(_) => assistant += 1,
+                ChatCompletionRequestMessage::Tool(_)
+                | ChatCompletionRequestMessage::Function(_) => tool += 1,
+            }
+        }
+        let tokens = utils::count_tokens(
+            &serde_json::to_string(&self.history).unwrap(),
+            &self.config.openai.model,
+        );
+        let window = utils::context_window_size(&self.config.openai.model);
+        let system_prompt = match self.history.first() {
+            Some(ChatCompletionRequestMessage::System(m)) => {
+                let s = m.content.split_whitespace().collect::<Vec<_>>().join(" ");
+                s.chars().take(50).collect::<String>()
+            }
+            _ => "".to_owned(),
+        };
+        let text = format!(
+            "|:-|:-|\n\
+             |**Messages**|{} system, {} user, {} assistant, {} tool|\n\
+             |**Estimated tokens**|~{}|\n\
+             |**Context window**|{:.1}% of {} ({})|\n\
+             |**System prompt**|{}...|\n\
+             |-\n",
+            system,
+            user,
+            assistant,
+            tool,
+            tokens,
+            tokens as f64 * 100.0 / window as f64,
+            window,
+            self.config.openai.model,
+            system_prompt,
+        );
+        MadSkin::default().print_text(&text);
+    }
+
+    /// Print the tokens used in this session, and the estimated cost
+    fn print_usage(&self) {
+        let model = &self.config.openai.model;
+        let cost = match utils::model_price_per_million_tokens(model) {
+            Some((input, output)) => format!(
+                "${:.4}",
+                (self.total_prompt_tokens as f64 * input
+                    + self.total_completion_tokens as f64 * output)
+                    / 1_000_000.0
+            ),
+            None => format!("unknown (no price for {})", model),
+        };
+        let text = format!(
+            "|:-|-:|\n\
+             |**prompt tokens**|{}|\n\
+             |**completion tokens**|{}|\n\
+             |**total tokens**|{}|\n\
+             |**estimated cost**|{}|\n\
+             |-\n",
+            self.total_prompt_tokens,
+            self.total_completion_tokens,
+            self.total_prompt_tokens + self.total_completion_tokens,
+            cost,
+        );
+        MadSkin::default().print_text(&text);
+    }
+
+    /// Print the estimated number of tokens in the current conversation, by role
+    fn print_token_budget(&self) {
+        // (tokens, messages) for system, user, assistant and tool messages
+        let mut counts = [(0, 0); 4];
+        for message in &self.history {
+            let index = match message {
+                ChatCompletionRequestMessage::System(_) => 0,
+                ChatCompletionRequestMessage::User(_) => 1,
+                ChatCompletionRequestMessage::Assistant(_) => 2,
+                ChatCompletionRequestMessage::Tool(_)
+                | ChatCompletionRequestMessage::Function(_) => 3,
+            };
+            let message = serde_json::to_string(message).unwrap();
+            counts[index].0 += utils::count_tokens(&message, &self.config.openai.model);
+            counts[index].1 += 1;
+        }
+        let total = counts.iter().map(|(tokens, _)| tokens).sum::<usize>();
+        let window = utils::context_window_size(&self.config.openai.model);
+        let text = format!(
+            "|:-|-:|\n\
+             |**system**|{}|\n\
+             |**user**|{} ({} turns)|\n\
+             |**assistant**|{} ({} turns)|\n\
+             |**tool**|{} ({} results)|\n\
+             |**total**|{}|\n\
+             |**remaining**|{} (assuming {} context window)|\n\
+             |-\n",
+            counts[0].0,
+            counts[1].0,
+            counts[1].1,
+            counts[2].0,
+            counts[2].1,
+            counts[3].0,
+            counts[3].1,
+            total,
+            window.saturating_sub(total),
+            self.config.openai.model,
+        );
+        MadSkin::default().print_text(&text);
+    }
+
+    /// Set a flag when the terminal hangs up (`SIGHUP`), so the REPL can exit cleanly
+    fn watch_for_hangup() -> anyhow::Result<Arc<AtomicBool>> {
+        let hangup = Arc::new(AtomicBool::new(false));
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut sighup = signal(SignalKind::hangup())?;
+            let hangup = hangup.clone();
+            tokio::spawn(async move {
+                sighup.recv().await;
+                hangup.store(true, Ordering::SeqCst);
+            });
+        }
+        Ok(hangup)
+    }
+
+    /// Delete the temporary files created by tools in this session
+    pub fn cleanup_temp_files(&self) {
+        let tools = self.tools.read().unwrap();
+        for path in tools.temp_files.lock().unwrap().drain(..) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    /// Flush any pending output and exit with the conventional `128 + SIGHUP` status
+    fn exit_on_hangup(&mut self) -> ! {
+        self.cleanup_temp_files();
+        if self.config.session.persist {
+            let _ = self.save_history();
+        }
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+        std::process::exit(129)
+    }
+
+    /// Write the successful `run_command` tool calls of the conversation to a bash script,
+    /// commented with the assistant's explanation before them. Returns the number of commands.
+    fn export_script(&self, path: &Path) -> anyhow::Result<usize> {
+        let results = self
+            .history
+            .iter()
+            .filter_map(|m| match m {
+                ChatCompletionRequestMessage::Tool(t) => {
+                    Some((t.tool_call_id.as_str(), &t.content))
+                }
+                _ => None,
+            })
+            .collect::<std::collections::HashMap<_, _>>();
+        let mut script = "#!/bin/bash\n".to_owned();
+        let mut intent: Option<&str> = None;
+        let mut commented = false;
+        let mut count = 0;
+        for message in &self.history {
+            let ChatCompletionRequestMessage::Assistant(message) = message else {
+                continue;
+            };
+            if let Some(content) = message.content.as_deref().filter(|c| !c.trim().is_empty()) {
+                intent = Some(content);
+                commented = false;
+            }
+            for tool_call in message.tool_calls.iter().flatten() {
+                if tool_call.function.name != "run_command" {
+                    continue;
+                }
+                let Some(result) = results
+                    .get(tool_call.id.as_str())
+                    .and_then(|r| serde_json::from_str::<serde_json::Value>(r).ok())
+                    .filter(|r| r["status_code"].as_i64() == Some(0))
+                else {
+                    continue;
+                };
+                let args =
+                    serde_json::from_str::<serde_json::Value>(&tool_call.function.arguments)?;
+                // The user may have edited the command before running it
+                let Some(command) = result["edited_command"]
+                    .as_str()
+                    .or(args["command"].as_str())
+                else {
+                    continue;
+                };
+                script.push('\n');
+                if let Some(intent) = intent.filter(|_| !commented) {
+                    for line in intent.trim().lines() {
+                        script.push_str(format!("# {}", line).trim_end());
+                        script.push('\n');
+                    }
+                    commented = true;
+                }
+                script.push_str(command.trim());
+                script.push('\n');
+                count += 1;
+            }
+        }
+        std::fs::write(path, script)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+        }
+        Ok(count)
+    }
+
     pub async fn run_repl(&mut self) -> anyhow::Result<()> {
+        let hangup = Self::watch_for_hangup()?;
         loop {
-            let Some(prompt) = utils::read_user_prompt()? else {
+            if hangup.load(Ordering::SeqCst) {
+                self.exit_on_hangup();
+            }
+            let prompt = utils::read_user_prompt(&self.config.ui);
+            // Reading from a closed terminal may fail, so check for hangup first
+            if hangup.load(Ordering::SeqCst) {
+                self.exit_on_hangup();
+            }
+            let Some(prompt) = prompt? else {
                 return Ok(());
             };
             if prompt.trim().is_empty() {
@@ -186,6 +1261,59 @@ impl ShellSession {
             if prompt.trim() == "exit" {
                 return Ok(());
             }
+            if matches!(prompt.trim(), "clear" | "/reset") {
+                self.clear_history();
+                outputln!("{}", "Conversation cleared.".bright_black());
+                continue;
+            }
+            if prompt.trim() == "/context" {
+                self.print_context_stats();
+                continue;
+            }
+            if let Some(args) = prompt.trim().strip_prefix("/last") {
+                match self.last_response.as_deref() {
+                    None => outputln!("{}", "No response yet.".bright_black()),
+                    Some(response) if args.trim() == "--copy" => {
+                        utils::copy_to_clipboard(response)?;
+                        outputln!("{}", "Copied to clipboard.".bright_black());
+                    }
+                    Some(response) => self.print_assistant_output(response),
+                }
+                continue;
+            }
+            if matches!(prompt.trim(), "/usage" | "/cost") {
+                self.print_usage();
+                continue;
+            }
+            if let Some(file) = prompt.trim().strip_prefix("/export-script") {
+                if file.trim().is_empty() {
+                    outputln!("{}", "Usage: /export-script <FILE>".bright_black());
+                    continue;
+                }
+                let path = utils::expand_home(Path::new(file.trim()));
+                match self.export_script(&path) {
+                    Ok(count) => outputln!(
+                        "{}",
+                        format!("Exported {} commands to {}.", count, path.display())
+                            .bright_black()
+                    ),
+                    Err(e) => outputln!("{}", format!("Failed to export script: {}", e).red()),
+                }
+                continue;
+            }
+            if prompt.trim() == "/tokens" {
+                self.print_token_budget();
+                continue;
+            }
+            if self.config.is_potential_injection(&prompt) {
+                outputln!(
+                    "{}",
+                    "⚠ This prompt may contain injection patterns.".yellow()
+                );
+                if !utils::wait_for_user_acknowledgement() {
+                    continue;
+                }
+            }
             self.run_prompt(&prompt).await?;
         }
     }
@@ -196,6 +1324,7 @@ impl ShellSession {
     }
 
     pub async fn run_script(&mut self, script_file: &str) -> anyhow::Result<()> {
+        self.yes |= self.config.script.default_yes;
         let file = std::fs::File::open(script_file)?;
         let mut paragraph = "".to_owned();
         for line in BufReader::new(file).lines() {
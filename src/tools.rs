@@ -1,7 +1,13 @@
 use std::{
-    io::{self, BufRead, BufReader},
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
     process::Stdio,
-    sync::atomic::{AtomicBool, Ordering},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
 };
 
 use async_openai::types::{
@@ -11,44 +17,70 @@ use colored::Colorize;
 use once_cell::sync::Lazy;
 use serde_json::{json, Map, Value};
 
-use crate::{builtins, utils};
+use crate::{
+    builtins,
+    config::{Config, Permissions},
+    output, outputln, utils,
+};
+
+pub type ToolHandler = dyn Fn(&Tools, Value) -> Result<String, ToolError> + Sync + Send;
 
 pub struct GPTFunction {
     pub name: &'static str,
     pub desc: &'static str,
     pub params: Vec<Param>,
-    pub handler: Box<dyn Fn(Value) -> Result<String, ToolError> + Sync + Send>,
+    pub handler: Box<ToolHandler>,
 }
 
 impl GPTFunction {
-    fn get_info(&self) -> anyhow::Result<ChatCompletionTool> {
+    pub fn name(&self) -> &str {
+        self.name
+    }
+
+    pub fn description(&self) -> &str {
+        self.desc
+    }
+
+    pub fn params(&self) -> &[Param] {
+        &self.params
+    }
+
+    /// JSON schema of the parameters, shared by all API backends
+    pub fn parameters_schema(&self) -> Value {
         let param_props = self
-            .params
+            .params()
             .iter()
             .map(|param| {
                 let mut props = Map::new();
                 props.insert("type".to_string(), json!(param.ty));
                 props.insert("description".to_string(), json!(param.desc));
+                if param.ty == "array" {
+                    props.insert("items".to_string(), json!({ "type": "string" }));
+                }
                 (param.name.to_string(), json!(props))
             })
             .collect::<Map<String, Value>>();
         let required_params = self
-            .params
+            .params()
             .iter()
             .filter(|param| param.required)
             .map(|param| param.name.to_string())
             .collect::<Vec<String>>();
+        json!({
+            "type": "object",
+            "properties": param_props,
+            "required": required_params,
+        })
+    }
+
+    fn get_info(&self) -> anyhow::Result<ChatCompletionTool> {
         Ok(ChatCompletionToolArgs::default()
             .r#type(ChatCompletionToolType::Function)
             .function(
                 FunctionObjectArgs::default()
-                    .name(self.name)
-                    .description(self.desc)
-                    .parameters(json!({
-                        "type": "object",
-                        "properties": param_props,
-                        "required": required_params,
-                    }))
+                    .name(self.name())
+                    .description(self.description())
+                    .parameters(self.parameters_schema())
                     .build()?,
             )
             .build()?)
@@ -78,13 +110,31 @@ impl Param {
 }
 
 pub enum ToolError {
-    Aborted,
+    /// The user declined to run the tool
+    UserCancelled,
+    /// The tool could not be run, e.g. due to invalid arguments
+    ExecutionFailed(String),
 }
 
 pub struct Tools {
     tools: Vec<&'static GPTFunction>,
     pub yes: AtomicBool,
     pub quiet: AtomicBool,
+    /// Show commands and file changes without making them
+    pub dry_run: AtomicBool,
+    /// ID of the tool call being executed
+    pub tool_call_id: Mutex<String>,
+    /// Exposed to child commands as `GPTSH_SESSION_ID`
+    pub session_id: String,
+    pub permissions: Permissions,
+    /// Maximum length of the command output sent back to the model
+    pub max_output_chars: usize,
+    /// Temporary files created by tools, deleted when the session exits
+    pub temp_files: Mutex<Vec<PathBuf>>,
+    /// Extra patterns of environment variables hidden from the `env` builtin
+    pub sensitive_env_patterns: Vec<String>,
+    /// Ask for confirmation before built-in commands too
+    pub confirm_all_commands: bool,
 }
 
 impl Tools {
@@ -93,6 +143,14 @@ impl Tools {
             tools: tools.to_vec(),
             yes: AtomicBool::new(false),
             quiet: AtomicBool::new(false),
+            dry_run: AtomicBool::new(false),
+            tool_call_id: Mutex::new(String::new()),
+            session_id: String::new(),
+            permissions: Permissions::default(),
+            max_output_chars: usize::MAX,
+            temp_files: Mutex::new(vec![]),
+            sensitive_env_patterns: vec![],
+            confirm_all_commands: false,
         }
     }
 
@@ -105,11 +163,14 @@ impl Tools {
 
     pub fn run(&self, name: &str, params: Value) -> Result<String, ToolError> {
         for tool in &self.tools {
-            if tool.name == name {
-                return (tool.handler)(params);
+            if tool.name() == name {
+                return (tool.handler)(self, params);
             }
         }
-        unreachable!()
+        Err(ToolError::ExecutionFailed(format!(
+            "unknown tool: {}",
+            name
+        )))
     }
 }
 
@@ -119,39 +180,92 @@ static RUN_COMMAND: Lazy<GPTFunction> = Lazy::new(|| {
         desc: "Run a one-liner bash command",
         params: vec![
             Param::new("command", "string", true, "The one-liner bash command to execute. This will be directly sent to `bash -c ...` so be careful with the quotes escaping!"),
+            Param::new("env_inherit", "boolean", false, "Whether the command inherits the environment variables of the shell. Set to false to run in a clean environment. Defaults to true"),
+            Param::new("capture_only", "boolean", false, "Only capture the output for yourself, without showing it to the user. Use this for commands that only extract data. Defaults to false"),
         ],
-        handler: Box::new(|params| -> Result<String, ToolError> {
+        handler: Box::new(|tools, params| -> Result<String, ToolError> {
             let command = params["command"].as_str().unwrap().trim();
+            let env_inherit = params["env_inherit"].as_bool().unwrap_or(true);
+            let capture_only = params["capture_only"].as_bool().unwrap_or(false);
+            let show_output = !capture_only && !tools.quiet.load(Ordering::SeqCst);
             // Show command and get user confirmation before executing
-            println!("{} {}", "➜".green().bold(), command.bold());
-            // Special handling for built-in commands
-            if builtins::is_built_in_command(command) {
-                let json = match builtins::execute_built_in_command(command) {
-                    Ok(_) => json!({
-                        "status_code": 0,
-                        "stdout": "",
-                        "stderr": "",
-                    }),
-                    Err(e) => json!({
-                        "status_code": 1,
-                        "stdout": "",
-                        "stderr": e.to_string(),
-                    }),
-                };
+            outputln!("{} {}", "➜".green().bold(), command.bold());
+            if let Err(reason) = tools.permissions.check_command(command) {
+                return Ok(blocked_by_policy(&reason));
+            }
+            if tools.dry_run.load(Ordering::SeqCst) {
+                let json = json!({
+                    "status_code": 0,
+                    "stdout": "Dry run: the command was not executed. Assume it succeeded.",
+                    "stderr": "",
+                    "host": whoami::hostname(),
+                });
                 return Ok(json.to_string());
             }
-            // User confirmation before executing
-            if !TOOLS.yes.load(Ordering::SeqCst) && !utils::wait_for_user_acknowledgement() {
-                return Err(ToolError::Aborted);
+            // Read-only built-in commands run without confirmation, unless `confirm_all_commands` is set
+            if builtins::is_built_in_command(command)
+                && !builtins::needs_confirmation(command)
+                && !tools.confirm_all_commands
+            {
+                return Ok(run_built_in_command(tools, command));
+            }
+            // User confirmation before executing. The user may also edit the command.
+            let edited_command = if tools.yes.load(Ordering::SeqCst) {
+                None
+            } else {
+                match utils::wait_for_user_acknowledgement_or_edit(command) {
+                    None => return Err(ToolError::UserCancelled),
+                    Some(c) if c != command => Some(c),
+                    Some(_) => None,
+                }
+            };
+            let command = match edited_command.as_deref() {
+                Some(edited) => {
+                    outputln!("{} {}", "➜".green().bold(), edited.bold());
+                    if let Err(reason) = tools.permissions.check_command(edited) {
+                        return Ok(blocked_by_policy(&reason));
+                    }
+                    edited
+                }
+                None => command,
+            };
+            if builtins::is_built_in_command(command) {
+                return Ok(run_built_in_command(tools, command));
             }
             // Execute command
-            let mut child = std::process::Command::new("bash")
+            let mut cmd = std::process::Command::new("bash");
+            if !env_inherit {
+                cmd.env_clear();
+            }
+            cmd.env("GPTSH_SESSION_ID", &tools.session_id).env(
+                "GPTSH_TOOL_CALL_ID",
+                tools.tool_call_id.lock().unwrap().as_str(),
+            );
+            let timeout = tools.permissions.command_timeout_secs.map(Duration::from_secs);
+            // Run in a new process group, so the whole group can be killed on timeout
+            #[cfg(unix)]
+            if timeout.is_some() {
+                use std::os::unix::process::CommandExt;
+                cmd.process_group(0);
+            }
+            // Report the final directory of the command, so a `cd` inside it persists for later commands
+            let cwd_file = tempfile::NamedTempFile::new()
+                .map_err(|e| ToolError::ExecutionFailed(format!("failed to create temp file: {}", e)))?;
+            cmd.env("GPTSH_CWD_FILE", cwd_file.path());
+            // Appended rather than trapped on exit, so the command's own traps are kept
+            let script = format!(
+                "{}\n__gptsh_status=$?; pwd > \"$GPTSH_CWD_FILE\"; exit $__gptsh_status",
+                command
+            );
+            let start = std::time::Instant::now();
+            let mut child = cmd
+                .current_dir(std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")))
                 .arg("-c")
-                .arg(command)
+                .arg(script)
                 .stderr(Stdio::piped())
                 .stdout(Stdio::piped())
                 .spawn()
-                .unwrap();
+                .map_err(|e| ToolError::ExecutionFailed(format!("failed to start bash: {}", e)))?;
             let child_stdout = child.stdout.take().unwrap();
             let child_stderr = child.stderr.take().unwrap();
             let (status, stdout, stderr) = std::thread::scope(|s| {
@@ -160,8 +274,8 @@ static RUN_COMMAND: Lazy<GPTFunction> = Lazy::new(|| {
                     let mut result = "".to_owned();
                     for line in lines {
                         let line = line.unwrap();
-                        if !TOOLS.quiet.load(Ordering::SeqCst) {
-                            println!("{}", line.bright_black());
+                        if show_output {
+                            outputln!("{}", line.bright_black());
                         }
                         result.push_str(&line);
                         result.push('\n');
@@ -173,7 +287,7 @@ static RUN_COMMAND: Lazy<GPTFunction> = Lazy::new(|| {
                     let mut result = "".to_owned();
                     for line in lines {
                         let line = line.unwrap();
-                        if !TOOLS.quiet.load(Ordering::SeqCst) {
+                        if show_output {
                             eprintln!("{}", line.bright_black());
                         }
                         result.push_str(&line);
@@ -181,21 +295,116 @@ static RUN_COMMAND: Lazy<GPTFunction> = Lazy::new(|| {
                     }
                     Ok(result)
                 });
-                let status = child.wait().unwrap();
+                let status = match timeout {
+                    Some(timeout) => wait_with_timeout(&mut child, timeout),
+                    None => child.wait().map(Some),
+                }
+                .unwrap();
                 let stdout = stdout_thread.join().unwrap().unwrap();
                 let stderr = stderr_thread.join().unwrap().unwrap();
                 (status, stdout, stderr)
             });
-            let json = json!({
-                "status_code": status.code().unwrap(),
-                "stdout": stdout,
-                "stderr": stderr,
+            if let Ok(new_cwd) = std::fs::read_to_string(cwd_file.path()) {
+                let new_cwd = Path::new(new_cwd.trim_end_matches('\n'));
+                if !new_cwd.as_os_str().is_empty()
+                    && std::env::current_dir().is_ok_and(|cwd| cwd != new_cwd)
+                {
+                    let _ = builtins::change_dir(new_cwd);
+                }
+            }
+            let elapsed = start.elapsed();
+            if !tools.quiet.load(Ordering::SeqCst) && elapsed.as_secs() >= 1 {
+                outputln!(
+                    "{}",
+                    format!("(took {})", utils::format_duration(elapsed)).bright_black()
+                );
+            }
+            let (status_code, stderr) = match status {
+                Some(status) => (status.code().unwrap_or(-1), stderr),
+                None => (
+                    -1,
+                    format!(
+                        "{}gptsh: command timed out after {}s and was killed\n",
+                        stderr,
+                        timeout.unwrap().as_secs()
+                    ),
+                ),
+            };
+            // The user has seen the full output, only the copy sent to the model is truncated
+            let mut json = json!({
+                "status_code": status_code,
+                "stdout": utils::truncate_middle(&stdout, tools.max_output_chars),
+                "stderr": utils::truncate_middle(&stderr, tools.max_output_chars),
+                "host": whoami::hostname(),
             });
+            // Let the model know what was actually run
+            if edited_command.is_some() {
+                json["edited_command"] = json!(command);
+            }
             Ok(json.to_string())
         }),
     }
 });
 
+/// The result of a state-changing tool under `--dry-run`: the result it would have returned,
+/// with a note that nothing was changed
+fn dry_run_result(mut json: Value) -> String {
+    json["dry_run"] = json!(true);
+    json["note"] = json!("Dry run: nothing was changed. Assume it succeeded.");
+    json.to_string()
+}
+
+/// Run a built-in command, with the same result format as a bash command
+fn run_built_in_command(tools: &Tools, command: &str) -> String {
+    let json = match builtins::execute_built_in_command(command, &tools.sensitive_env_patterns) {
+        Ok((status_code, stdout)) => json!({
+            "status_code": status_code,
+            "stdout": stdout,
+            "stderr": "",
+            "host": whoami::hostname(),
+        }),
+        Err(e) => json!({
+            "status_code": 1,
+            "stdout": "",
+            "stderr": e.to_string(),
+            "host": whoami::hostname(),
+        }),
+    };
+    json.to_string()
+}
+
+/// The tool result of a command blocked by the `[permissions]` allow or deny lists
+fn blocked_by_policy(reason: &str) -> String {
+    outputln!("{}", format!("Blocked by policy: {}", reason).yellow());
+    let json = json!({
+        "error": format!("The command was blocked by the user's permission policy: it {}. Do not retry it.", reason),
+    });
+    json.to_string()
+}
+
+/// Wait for the child to exit. Kill its process group and return `None` if it does not exit within `timeout`.
+fn wait_with_timeout(
+    child: &mut std::process::Child,
+    timeout: Duration,
+) -> io::Result<Option<std::process::ExitStatus>> {
+    let deadline = std::time::Instant::now() + timeout;
+    while std::time::Instant::now() < deadline {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    #[cfg(unix)]
+    {
+        use nix::sys::signal::{killpg, Signal};
+        use nix::unistd::Pid;
+        let _ = killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL);
+    }
+    let _ = child.kill();
+    child.wait()?;
+    Ok(None)
+}
+
 static CHANGE_CWD: Lazy<GPTFunction> = Lazy::new(|| GPTFunction {
     name: "chdir",
     desc: "Changes the current working directory of the terminal to another directory",
@@ -205,10 +414,10 @@ static CHANGE_CWD: Lazy<GPTFunction> = Lazy::new(|| GPTFunction {
         true,
         "The path to the new working directory",
     )],
-    handler: Box::new(|params| -> Result<String, ToolError> {
+    handler: Box::new(|_tools, params| -> Result<String, ToolError> {
         let path = params["path"].as_str().unwrap().trim();
-        println!("{} {}", "➜".blue().bold(), format!("cd {path}").bold());
-        let result = match std::env::set_current_dir(path) {
+        outputln!("{} {}", "➜".blue().bold(), format!("cd {path}").bold());
+        let result = match builtins::change_dir(Path::new(path)) {
             Ok(_) => "done".to_string(),
             Err(e) => format!("chdir error: {}", e),
         };
@@ -216,10 +425,1268 @@ static CHANGE_CWD: Lazy<GPTFunction> = Lazy::new(|| GPTFunction {
     }),
 });
 
-pub static TOOLS: Lazy<Tools> = Lazy::new(|| {
-    Tools::new(&[
-        &RUN_COMMAND,
-        &CHANGE_CWD,
-        // Add more tools here
-    ])
+static LIST_DIRECTORY: Lazy<GPTFunction> = Lazy::new(|| {
+    GPTFunction {
+    name: "list_directory",
+    desc: "List the entries of a directory. Returns a JSON array of entries with their name, type, size and modification time, sorted by name",
+    params: vec![
+        Param::new(
+            "path",
+            "string",
+            false,
+            "The directory to list. Defaults to the current working directory",
+        ),
+        Param::new(
+            "show_hidden",
+            "boolean",
+            false,
+            "Whether to include hidden entries (names starting with '.'). Defaults to false",
+        ),
+    ],
+    handler: Box::new(|_tools, params| -> Result<String, ToolError> {
+        let path = params["path"].as_str().unwrap_or(".").trim();
+        let path = if path.is_empty() { "." } else { path };
+        let show_hidden = params["show_hidden"].as_bool().unwrap_or(false);
+        outputln!("{} {}", "➜".blue().bold(), format!("ls {path}").bold());
+        let read_dir = match std::fs::read_dir(path) {
+            Ok(read_dir) => read_dir,
+            Err(e) => return Ok(json!({ "error": format!("list_directory error: {}", e) }).to_string()),
+        };
+        let mut entries = vec![];
+        for entry in read_dir.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !show_hidden && name.starts_with('.') {
+                continue;
+            }
+            let Ok(metadata) = entry.path().symlink_metadata() else {
+                continue;
+            };
+            let ty = if metadata.file_type().is_symlink() {
+                "symlink"
+            } else if metadata.is_dir() {
+                "dir"
+            } else {
+                "file"
+            };
+            let modified = metadata
+                .modified()
+                .ok()
+                .map(|t| chrono::DateTime::<chrono::Utc>::from(t).to_rfc3339());
+            entries.push(json!({
+                "name": name,
+                "type": ty,
+                "size_bytes": metadata.len(),
+                "modified": modified,
+            }));
+        }
+        entries.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+        Ok(Value::Array(entries).to_string())
+    }),
+}
+});
+
+/// Recursively collect all the files under `path`.
+/// Symlinks are collected as they are and never followed, so a link to a parent cannot loop.
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if std::fs::symlink_metadata(path)?.is_dir() {
+        let mut entries = std::fs::read_dir(path)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+        for entry in entries {
+            collect_files(&entry, files)?;
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+fn create_archive(paths: &[&str], output: &str, format: &str) -> anyhow::Result<usize> {
+    if !matches!(format, "tar.gz" | "tar.bz2" | "zip") {
+        anyhow::bail!("unsupported archive format: {}", format);
+    }
+    let mut files = vec![];
+    for path in paths {
+        collect_files(Path::new(path), &mut files)?;
+    }
+    // Archive entries are always relative paths
+    let entry_name = |file: &Path| -> PathBuf {
+        file.components()
+            .filter(|c| matches!(c, std::path::Component::Normal(_)))
+            .collect()
+    };
+    let out = File::create(output)?;
+    match format {
+        "tar.gz" | "tar.bz2" => {
+            let encoder: Box<dyn io::Write> = if format == "tar.gz" {
+                Box::new(flate2::write::GzEncoder::new(
+                    out,
+                    flate2::Compression::default(),
+                ))
+            } else {
+                Box::new(bzip2::write::BzEncoder::new(
+                    out,
+                    bzip2::Compression::default(),
+                ))
+            };
+            let mut builder = tar::Builder::new(encoder);
+            builder.follow_symlinks(false);
+            for file in &files {
+                builder.append_path_with_name(file, entry_name(file))?;
+            }
+            builder.into_inner()?.flush()?;
+        }
+        "zip" => {
+            let mut writer = zip::ZipWriter::new(out);
+            let options = zip::write::SimpleFileOptions::default();
+            for file in &files {
+                let name = entry_name(file).to_string_lossy().into_owned();
+                if file.is_symlink() {
+                    writer.add_symlink_from_path(name, std::fs::read_link(file)?, options)?;
+                    continue;
+                }
+                writer.start_file(name, options)?;
+                io::copy(&mut File::open(file)?, &mut writer)?;
+            }
+            writer.finish()?;
+        }
+        _ => unreachable!(),
+    }
+    Ok(files.len())
+}
+
+static COMPRESS_FILE: Lazy<GPTFunction> = Lazy::new(|| GPTFunction {
+    name: "compress_file",
+    desc: "Create a .tar.gz, .tar.bz2 or .zip archive from a list of files and directories",
+    params: vec![
+        Param::new(
+            "paths",
+            "array",
+            true,
+            "The files and directories to include in the archive",
+        ),
+        Param::new(
+            "output",
+            "string",
+            true,
+            "The path of the archive to create",
+        ),
+        Param::new(
+            "format",
+            "string",
+            true,
+            "The archive format. One of \"tar.gz\", \"tar.bz2\" or \"zip\"",
+        ),
+    ],
+    handler: Box::new(|tools, params| -> Result<String, ToolError> {
+        let paths = params["paths"]
+            .as_array()
+            .map(|a| a.iter().filter_map(|p| p.as_str()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        let output = params["output"].as_str().unwrap().trim();
+        let format = params["format"].as_str().unwrap().trim();
+        outputln!(
+            "{} {}",
+            "➜".green().bold(),
+            format!("compress {} -> {output} ({format})", paths.join(" ")).bold()
+        );
+        if tools.dry_run.load(Ordering::SeqCst) {
+            return Ok(dry_run_result(json!({ "output": output })));
+        }
+        // User confirmation before executing
+        if !tools.yes.load(Ordering::SeqCst) && !utils::wait_for_user_acknowledgement() {
+            return Err(ToolError::UserCancelled);
+        }
+        let json = match create_archive(&paths, output, format) {
+            Ok(files_added) => json!({
+                "output": output,
+                "bytes": std::fs::metadata(output).map(|m| m.len()).unwrap_or(0),
+                "files_added": files_added,
+            }),
+            Err(e) => json!({ "error": format!("compress_file error: {}", e) }),
+        };
+        Ok(json.to_string())
+    }),
+});
+
+/// Resolve `path` without following a trailing symlink, and check that it is inside the current working directory
+fn resolve_path_in_cwd(path: &Path) -> anyhow::Result<PathBuf> {
+    let cwd = std::env::current_dir()?.canonicalize()?;
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("invalid path: {}", path.display()))?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.canonicalize()?,
+        _ => cwd.clone(),
+    };
+    let resolved = parent.join(file_name);
+    if !resolved.starts_with(&cwd) || resolved == cwd {
+        anyhow::bail!(
+            "refusing to delete {} outside the current working directory",
+            resolved.display()
+        );
+    }
+    Ok(resolved)
+}
+
+static READ_FILE: Lazy<GPTFunction> = Lazy::new(|| {
+    GPTFunction {
+    name: "read_file",
+    desc: "Read a text file, or a range of its lines. Only files inside the current working directory can be read",
+    params: vec![
+        Param::new("path", "string", true, "The file to read"),
+        Param::new(
+            "start_line",
+            "integer",
+            false,
+            "The first line to read, starting from 1. Defaults to 1",
+        ),
+        Param::new(
+            "end_line",
+            "integer",
+            false,
+            "The last line to read (inclusive). Defaults to the end of the file",
+        ),
+    ],
+    handler: Box::new(|tools, params| -> Result<String, ToolError> {
+        let path = params["path"].as_str().unwrap().trim();
+        let start_line = params["start_line"].as_u64().unwrap_or(1).max(1) as usize;
+        let end_line = params["end_line"].as_u64().map(|l| l as usize);
+        outputln!("{} {}", "➜".blue().bold(), format!("cat {path}").bold());
+        let allow_outside_cwd = tools.permissions.read_outside_cwd;
+        let result = read_file_lines(path, start_line, end_line, allow_outside_cwd);
+        let json = result.unwrap_or_else(|e| json!({ "error": format!("read_file error: {}", e) }));
+        Ok(json.to_string())
+    }),
+}
+});
+
+/// Read lines `start_line..=end_line` (1-based) of a file, truncated to a maximum size
+fn read_file_lines(
+    path: &str,
+    start_line: usize,
+    end_line: Option<usize>,
+    allow_outside_cwd: bool,
+) -> anyhow::Result<Value> {
+    const MAX_BYTES: usize = 64 * 1024;
+    let resolved = utils::expand_home(Path::new(path)).canonicalize()?;
+    let cwd = std::env::current_dir()?.canonicalize()?;
+    if !allow_outside_cwd && !resolved.starts_with(&cwd) {
+        anyhow::bail!(
+            "refusing to read {} outside the current working directory",
+            resolved.display()
+        );
+    }
+    let content = std::fs::read_to_string(&resolved)?;
+    let total_lines = content.lines().count();
+    let mut selected = content
+        .lines()
+        .skip(start_line - 1)
+        .take(end_line.map_or(usize::MAX, |end| (end + 1).saturating_sub(start_line)))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let truncated = selected.len() > MAX_BYTES;
+    if truncated {
+        let mut end = MAX_BYTES;
+        while !selected.is_char_boundary(end) {
+            end -= 1;
+        }
+        selected.truncate(end);
+    }
+    let mut json = json!({
+        "path": path,
+        "content": selected,
+        "total_lines": total_lines,
+    });
+    if truncated {
+        json["note"] = json!(format!(
+            "Truncated to {} bytes. Use start_line and end_line to read the rest",
+            MAX_BYTES
+        ));
+    }
+    Ok(json)
+}
+
+static WRITE_FILE: Lazy<GPTFunction> = Lazy::new(|| {
+    GPTFunction {
+    name: "write_file",
+    desc: "Create or overwrite a text file. The user reviews a diff of the change before it is written",
+    params: vec![
+        Param::new("path", "string", true, "The file to write"),
+        Param::new("content", "string", true, "The full new content of the file"),
+    ],
+    handler: Box::new(|tools, params| -> Result<String, ToolError> {
+        let path = params["path"].as_str().unwrap().trim();
+        let content = params["content"].as_str().unwrap();
+        let resolved = utils::expand_home(Path::new(path));
+        let old_content = match std::fs::read_to_string(&resolved) {
+            Ok(old_content) => Some(old_content),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Ok(json!({ "error": format!("write_file error: {}", e) }).to_string()),
+        };
+        let status = if old_content.is_some() { "" } else { " (new file)" };
+        outputln!("{} {}", "➜".green().bold(), format!("write {path}{status}").bold());
+        if !tools.quiet.load(Ordering::SeqCst) {
+            print_diff(old_content.as_deref().unwrap_or_default(), content, path);
+        }
+        if tools.dry_run.load(Ordering::SeqCst) {
+            return Ok(dry_run_result(json!({ "path": path, "bytes_written": content.len() })));
+        }
+        // User confirmation before writing
+        if !tools.yes.load(Ordering::SeqCst) && !utils::wait_for_user_acknowledgement() {
+            return Err(ToolError::UserCancelled);
+        }
+        let json = match std::fs::write(&resolved, content) {
+            Ok(_) => json!({ "path": path, "bytes_written": content.len() }),
+            Err(e) => json!({ "error": format!("write_file error: {}", e) }),
+        };
+        Ok(json.to_string())
+    }),
+}
+});
+
+/// Print a colored unified diff between the old and new content of a file
+fn print_diff(old: &str, new: &str, path: &str) {
+    let diff = similar::TextDiff::from_lines(old, new);
+    let diff = diff
+        .unified_diff()
+        .context_radius(3)
+        .header(&format!("a/{path}"), &format!("b/{path}"))
+        .to_string();
+    for line in diff.lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            outputln!("{}", line.bold());
+        } else if line.starts_with('+') {
+            outputln!("{}", line.green());
+        } else if line.starts_with('-') {
+            outputln!("{}", line.red());
+        } else if line.starts_with("@@") {
+            outputln!("{}", line.cyan());
+        } else {
+            outputln!("{}", line);
+        }
+    }
+}
+
+static WRITE_TEMP_FILE: Lazy<GPTFunction> = Lazy::new(|| {
+    GPTFunction {
+    name: "write_temp_file",
+    desc: "Write content to a new temporary file and return its path. The file is deleted when gptsh exits",
+    params: vec![
+        Param::new("content", "string", true, "The content of the file"),
+        Param::new("prefix", "string", false, "The file name prefix. Defaults to \"gptsh\""),
+        Param::new(
+            "suffix",
+            "string",
+            false,
+            "The file name suffix, e.g. \".py\". Defaults to none",
+        ),
+    ],
+    handler: Box::new(|tools, params| -> Result<String, ToolError> {
+        let content = params["content"].as_str().unwrap();
+        let prefix = params["prefix"].as_str().unwrap_or("gptsh");
+        let suffix = params["suffix"].as_str().unwrap_or("");
+        if tools.dry_run.load(Ordering::SeqCst) {
+            let path = std::env::temp_dir().join(format!("{prefix}XXXXXX{suffix}"));
+            outputln!("{} {}", "➜".blue().bold(), format!("write {}", path.display()).bold());
+            return Ok(dry_run_result(json!({ "path": path })));
+        }
+        let result = tempfile::Builder::new()
+            .prefix(prefix)
+            .suffix(suffix)
+            .tempfile()
+            .and_then(|mut file| {
+                file.write_all(content.as_bytes())?;
+                file.into_temp_path().keep().map_err(|e| e.error)
+            });
+        let json = match result {
+            Ok(path) => {
+                outputln!("{} {}", "➜".blue().bold(), format!("write {}", path.display()).bold());
+                tools.temp_files.lock().unwrap().push(path.clone());
+                json!({ "path": path })
+            }
+            Err(e) => json!({ "error": format!("write_temp_file error: {}", e) }),
+        };
+        Ok(json.to_string())
+    }),
+}
+});
+
+static DELETE_FILE: Lazy<GPTFunction> = Lazy::new(|| {
+    GPTFunction {
+    name: "delete_file",
+    desc: "Delete a file, or a directory and all its contents. Only paths inside the current working directory can be deleted",
+    params: vec![
+        Param::new("path", "string", true, "The file or directory to delete"),
+        Param::new(
+            "recursive",
+            "boolean",
+            false,
+            "Delete a directory and all its contents. Defaults to false",
+        ),
+    ],
+    handler: Box::new(|tools, params| -> Result<String, ToolError> {
+        let path = params["path"].as_str().unwrap().trim();
+        let recursive = params["recursive"].as_bool().unwrap_or(false);
+        let flags = if recursive { "-r " } else { "" };
+        outputln!("{} {}", "➜".red().bold(), format!("rm {flags}{path}").bold());
+        let resolved = match resolve_path_in_cwd(Path::new(path)) {
+            Ok(resolved) => resolved,
+            Err(e) => return Ok(json!({ "error": format!("delete_file error: {}", e) }).to_string()),
+        };
+        if tools.dry_run.load(Ordering::SeqCst) {
+            return Ok(dry_run_result(json!({ "deleted": true, "path": path })));
+        }
+        // Always confirm deletions, even with `--yes`
+        let confirmed = if recursive {
+            let name = resolved.file_name().unwrap().to_string_lossy();
+            utils::wait_for_user_typed_confirmation(&name)
+        } else {
+            utils::stdin_is_terminal() && utils::wait_for_user_acknowledgement()
+        };
+        if !confirmed {
+            return Err(ToolError::UserCancelled);
+        }
+        // Neither `remove_file` nor `remove_dir_all` follow symlinks
+        let result = if recursive && !resolved.is_symlink() {
+            std::fs::remove_dir_all(&resolved)
+        } else {
+            std::fs::remove_file(&resolved)
+        };
+        let json = match result {
+            Ok(_) => json!({ "deleted": true, "path": path }),
+            Err(e) => json!({ "error": format!("delete_file error: {}", e) }),
+        };
+        Ok(json.to_string())
+    }),
+}
+});
+
+static JSON_PATH: Lazy<GPTFunction> = Lazy::new(|| GPTFunction {
+    name: "json_path",
+    desc: "Extract values from a JSON document using a JSONPath expression",
+    params: vec![
+        Param::new("json", "string", true, "The JSON document"),
+        Param::new(
+            "path",
+            "string",
+            true,
+            "The JSONPath expression, e.g. `$.store.book[0].title`",
+        ),
+    ],
+    handler: Box::new(|_tools, params| -> Result<String, ToolError> {
+        let json = params["json"].as_str().unwrap();
+        let path = params["path"].as_str().unwrap().trim();
+        let json = match serde_json::from_str::<Value>(json) {
+            Ok(json) => json,
+            Err(e) => return Ok(json!({ "error": format!("invalid json: {}", e) }).to_string()),
+        };
+        let result = match jsonpath_lib::select(&json, path) {
+            Ok(results) => json!({ "results": results }),
+            Err(e) => json!({ "error": format!("json_path error: {}", e) }),
+        };
+        Ok(result.to_string())
+    }),
+});
+
+static YAML_TO_JSON: Lazy<GPTFunction> = Lazy::new(|| GPTFunction {
+    name: "yaml_to_json",
+    desc: "Convert a YAML document to JSON",
+    params: vec![Param::new("yaml", "string", true, "The YAML document")],
+    handler: Box::new(|_tools, params| -> Result<String, ToolError> {
+        let yaml = params["yaml"].as_str().unwrap();
+        let json = match serde_yaml::from_str::<Value>(yaml) {
+            Ok(value) => json!({ "result": value.to_string() }),
+            Err(e) => json!({ "error": format!("yaml_to_json error: {}", e) }),
+        };
+        Ok(json.to_string())
+    }),
 });
+
+static JSON_TO_YAML: Lazy<GPTFunction> = Lazy::new(|| GPTFunction {
+    name: "json_to_yaml",
+    desc: "Convert a JSON document to YAML",
+    params: vec![Param::new("json", "string", true, "The JSON document")],
+    handler: Box::new(|_tools, params| -> Result<String, ToolError> {
+        let json = params["json"].as_str().unwrap();
+        let result = serde_json::from_str::<Value>(json)
+            .map_err(|e| e.to_string())
+            .and_then(|value| serde_yaml::to_string(&value).map_err(|e| e.to_string()));
+        let json = match result {
+            Ok(yaml) => json!({ "result": yaml }),
+            Err(e) => json!({ "error": format!("json_to_yaml error: {}", e) }),
+        };
+        Ok(json.to_string())
+    }),
+});
+
+static TEMPLATE_FILE: Lazy<GPTFunction> = Lazy::new(|| {
+    GPTFunction {
+    name: "template_file",
+    desc: "Render a Tera (Jinja2-like) template file with the given variables, and optionally write the result to a file",
+    params: vec![
+        Param::new(
+            "template_path",
+            "string",
+            true,
+            "The path of the template file",
+        ),
+        Param::new(
+            "vars",
+            "object",
+            true,
+            "The variables used to render the template",
+        ),
+        Param::new(
+            "output_path",
+            "string",
+            false,
+            "If provided, write the rendered result to this file",
+        ),
+    ],
+    handler: Box::new(|tools, params| -> Result<String, ToolError> {
+        let template_path = params["template_path"].as_str().unwrap().trim();
+        let output_path = params["output_path"].as_str().map(|p| p.trim());
+        let rendered = match render_template_file(template_path, &params["vars"]) {
+            Ok(rendered) => rendered,
+            Err(e) => {
+                let json = json!({ "error": format!("template_file error: {}", e) });
+                return Ok(json.to_string());
+            }
+        };
+        let Some(output_path) = output_path else {
+            return Ok(json!({ "rendered": rendered }).to_string());
+        };
+        outputln!(
+            "{} {}",
+            "➜".green().bold(),
+            format!("render {template_path} > {output_path}").bold()
+        );
+        if tools.dry_run.load(Ordering::SeqCst) {
+            let json = json!({ "rendered": rendered, "output_path": output_path });
+            return Ok(dry_run_result(json));
+        }
+        // User confirmation before writing
+        if !tools.yes.load(Ordering::SeqCst) && !utils::wait_for_user_acknowledgement() {
+            return Err(ToolError::UserCancelled);
+        }
+        let json = match std::fs::write(utils::expand_home(Path::new(output_path)), &rendered) {
+            Ok(_) => json!({ "rendered": rendered, "output_path": output_path }),
+            Err(e) => json!({ "error": format!("template_file error: {}", e) }),
+        };
+        Ok(json.to_string())
+    }),
+}
+});
+
+fn render_template_file(template_path: &str, vars: &Value) -> anyhow::Result<String> {
+    let template = std::fs::read_to_string(utils::expand_home(Path::new(template_path)))?;
+    // Some models pass the variables as a JSON string
+    let vars = match vars {
+        Value::String(s) => serde_json::from_str(s)?,
+        Value::Null => json!({}),
+        v => v.clone(),
+    };
+    let context = tera::Context::from_value(vars)?;
+    Ok(tera::Tera::one_off(&template, &context, false)?)
+}
+
+static FORMAT_CODE: Lazy<GPTFunction> = Lazy::new(|| GPTFunction {
+    name: "format_code",
+    desc: "Format a code snippet with rustfmt, black, prettier or jq",
+    params: vec![
+        Param::new("code", "string", true, "The code to format"),
+        Param::new(
+            "language",
+            "string",
+            true,
+            "One of \"rust\", \"python\", \"javascript\", \"typescript\", \"json\" or \"yaml\"",
+        ),
+    ],
+    handler: Box::new(|_tools, params| -> Result<String, ToolError> {
+        let code = params["code"].as_str().unwrap();
+        let language = params["language"].as_str().unwrap().trim();
+        let json = match format_code(code, language) {
+            Ok(Some(formatted)) => json!({ "formatted": formatted }),
+            Ok(None) => json!({ "formatted": code, "formatter_not_found": true }),
+            Err(e) => json!({ "error": format!("format_code error: {}", e) }),
+        };
+        Ok(json.to_string())
+    }),
+});
+
+/// Format `code` in a temporary file. Returns `None` if the formatter is not installed.
+fn format_code(code: &str, language: &str) -> anyhow::Result<Option<String>> {
+    let (extension, program, args): (&str, &str, &[&str]) = match language.to_lowercase().as_str() {
+        "rust" => (".rs", "rustfmt", &["--edition", "2021"]),
+        "python" => (".py", "black", &["-q"]),
+        "javascript" => (".js", "prettier", &["--write", "--log-level", "warn"]),
+        "typescript" => (".ts", "prettier", &["--write", "--log-level", "warn"]),
+        "yaml" => (".yaml", "prettier", &["--write", "--log-level", "warn"]),
+        "json" => (".json", "jq", &["."]),
+        _ => anyhow::bail!("unsupported language: {}", language),
+    };
+    let mut file = tempfile::Builder::new().suffix(extension).tempfile()?;
+    file.write_all(code.as_bytes())?;
+    file.flush()?;
+    let output = match std::process::Command::new(program)
+        .args(args)
+        .arg(file.path())
+        .stdin(Stdio::null())
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if !output.status.success() {
+        anyhow::bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    // jq prints the result, the other formatters rewrite the file in place
+    if program == "jq" {
+        Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned()))
+    } else {
+        Ok(Some(std::fs::read_to_string(file.path())?))
+    }
+}
+
+static LINT_CODE: Lazy<GPTFunction> = Lazy::new(|| {
+    GPTFunction {
+    name: "lint_code",
+    desc: "Check a code snippet for errors and warnings with clippy (Rust), or pylint or flake8 (Python)",
+    params: vec![
+        Param::new("code", "string", true, "The code to check"),
+        Param::new("language", "string", true, "Either \"rust\" or \"python\""),
+    ],
+    handler: Box::new(|_tools, params| -> Result<String, ToolError> {
+        let code = params["code"].as_str().unwrap();
+        let language = params["language"].as_str().unwrap().trim();
+        let json = match lint_code(code, language) {
+            Ok(Some(issues)) if issues.is_empty() => json!({ "ok": true }),
+            Ok(Some(issues)) => json!({ "issues": issues }),
+            Ok(None) => json!({ "linter_not_found": true }),
+            Err(e) => json!({ "error": format!("lint_code error: {}", e) }),
+        };
+        Ok(json.to_string())
+    }),
+}
+});
+
+/// Lint `code` in a temporary file, trying each supported linter in order.
+/// Returns `None` if none of them is installed.
+fn lint_code(code: &str, language: &str) -> anyhow::Result<Option<Vec<Value>>> {
+    let (extension, linters): (&str, &[&str]) = match language.to_lowercase().as_str() {
+        "rust" => (".rs", &["clippy-driver", "rustc"]),
+        "python" => (".py", &["pylint", "flake8"]),
+        _ => anyhow::bail!("unsupported language: {}", language),
+    };
+    let mut file = tempfile::Builder::new().suffix(extension).tempfile()?;
+    file.write_all(code.as_bytes())?;
+    file.flush()?;
+    let out_dir = tempfile::tempdir()?;
+    for linter in linters {
+        let mut command = std::process::Command::new(linter);
+        match *linter {
+            "clippy-driver" | "rustc" => command
+                .args(["--edition", "2021", "--crate-type", "lib"])
+                .args(["--emit", "metadata", "--error-format", "json", "--out-dir"])
+                .arg(out_dir.path()),
+            "pylint" => command.args(["--output-format", "json"]),
+            _ => &mut command,
+        };
+        // Linters exit with a non-zero status when they find issues
+        let output = match command.arg(file.path()).stdin(Stdio::null()).output() {
+            Ok(output) => output,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let issues = match *linter {
+            "clippy-driver" | "rustc" => parse_rustc_diagnostics(&stderr),
+            "pylint" => parse_pylint_output(&stdout)?,
+            _ => parse_flake8_output(&stdout),
+        };
+        return Ok(Some(issues));
+    }
+    Ok(None)
+}
+
+/// Parse the `--error-format json` diagnostics of rustc, one JSON object per line
+fn parse_rustc_diagnostics(output: &str) -> Vec<Value> {
+    output
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|d| matches!(d["level"].as_str(), Some("error" | "warning")))
+        .filter_map(|d| {
+            // Summaries like "aborting due to 1 previous error" have no location
+            let span = d["spans"]
+                .as_array()?
+                .iter()
+                .find(|s| s["is_primary"].as_bool() == Some(true))?;
+            Some(json!({
+                "line": span["line_start"],
+                "severity": d["level"],
+                "message": d["message"],
+            }))
+        })
+        .collect()
+}
+
+/// Parse the `--output-format json` report of pylint
+fn parse_pylint_output(output: &str) -> anyhow::Result<Vec<Value>> {
+    let messages: Vec<Value> = serde_json::from_str(output)?;
+    Ok(messages
+        .iter()
+        .map(|m| {
+            let severity = match m["type"].as_str() {
+                Some("error" | "fatal") => "error",
+                _ => "warning",
+            };
+            json!({ "line": m["line"], "severity": severity, "message": m["message"] })
+        })
+        .collect())
+}
+
+/// Parse the `FILE:LINE:COLUMN: CODE MESSAGE` lines of flake8
+fn parse_flake8_output(output: &str) -> Vec<Value> {
+    output
+        .lines()
+        .filter_map(|line| {
+            // The temporary file path has no colons, but the message may
+            let mut parts = line.splitn(4, ':').skip(1);
+            let line = parts.next()?.parse::<u64>().ok()?;
+            let message = parts.nth(1)?.trim();
+            // W: pycodestyle warnings, C: complexity
+            let severity = match message.chars().next() {
+                Some('W' | 'C') => "warning",
+                _ => "error",
+            };
+            Some(json!({ "line": line, "severity": severity, "message": message }))
+        })
+        .collect()
+}
+
+static SLEEP: Lazy<GPTFunction> = Lazy::new(|| GPTFunction {
+    name: "sleep",
+    desc: "Wait for a number of seconds (at most 60), e.g. to give a server time to start",
+    params: vec![Param::new(
+        "seconds",
+        "number",
+        true,
+        "The number of seconds to sleep. Capped at 60",
+    )],
+    handler: Box::new(|tools, params| -> Result<String, ToolError> {
+        let seconds = params["seconds"].as_f64().unwrap_or(0.0).clamp(0.0, 60.0);
+        let mut remaining = Duration::from_millis((seconds * 1000.0) as u64);
+        let quiet = tools.quiet.load(Ordering::SeqCst);
+        while !remaining.is_zero() {
+            if !quiet {
+                output!(
+                    "\r{} {}",
+                    "➜".blue().bold(),
+                    format!("Sleeping for {}s…", remaining.as_secs_f64().ceil()).bold()
+                );
+            }
+            let step = remaining.min(Duration::from_secs(1));
+            std::thread::sleep(step);
+            remaining -= step;
+        }
+        if !quiet {
+            outputln!();
+        }
+        Ok(json!({ "slept_seconds": seconds }).to_string())
+    }),
+});
+
+fn encode_decode(operation: &str, input: &str) -> anyhow::Result<String> {
+    use base64::Engine;
+    let utf8 = |bytes: Vec<u8>| {
+        String::from_utf8(bytes).map_err(|_| anyhow::anyhow!("decoded bytes are not valid UTF-8"))
+    };
+    match operation {
+        "base64_encode" => Ok(base64::engine::general_purpose::STANDARD.encode(input)),
+        "base64_decode" => utf8(base64::engine::general_purpose::STANDARD.decode(input.trim())?),
+        "url_encode" => Ok(urlencoding::encode(input).into_owned()),
+        "url_decode" => Ok(urlencoding::decode(input)?.into_owned()),
+        "hex_encode" => Ok(input.bytes().map(|b| format!("{:02x}", b)).collect()),
+        "hex_decode" => {
+            let input = input.trim();
+            if !input.is_ascii() || !input.len().is_multiple_of(2) {
+                anyhow::bail!("invalid hex input");
+            }
+            let bytes = (0..input.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&input[i..i + 2], 16))
+                .collect::<Result<Vec<_>, _>>()?;
+            utf8(bytes)
+        }
+        _ => anyhow::bail!("unknown operation: {}", operation),
+    }
+}
+
+static ENCODE_DECODE: Lazy<GPTFunction> = Lazy::new(|| {
+    GPTFunction {
+    name: "encode_decode",
+    desc: "Encode or decode a string with base64, URL (percent) or hex encoding",
+    params: vec![
+        Param::new(
+            "operation",
+            "string",
+            true,
+            "One of \"base64_encode\", \"base64_decode\", \"url_encode\", \"url_decode\", \"hex_encode\" or \"hex_decode\"",
+        ),
+        Param::new("input", "string", true, "The string to encode or decode"),
+    ],
+    handler: Box::new(|_tools, params| -> Result<String, ToolError> {
+        let operation = params["operation"].as_str().unwrap().trim();
+        let input = params["input"].as_str().unwrap();
+        let json = match encode_decode(operation, input) {
+            Ok(result) => json!({ "result": result }),
+            Err(e) => json!({ "error": format!("encode_decode error: {}", e) }),
+        };
+        Ok(json.to_string())
+    }),
+}
+});
+
+/// Capture groups of a match, or the whole match if the pattern has no groups
+fn regex_captures(caps: &regex::Captures) -> Vec<Option<String>> {
+    let groups = if caps.len() > 1 { 1..caps.len() } else { 0..1 };
+    groups
+        .map(|i| caps.get(i).map(|m| m.as_str().to_owned()))
+        .collect()
+}
+
+static REGEX_MATCH: Lazy<GPTFunction> = Lazy::new(|| GPTFunction {
+    name: "regex_match",
+    desc: "Match a regular expression against a text and extract the capture groups",
+    params: vec![
+        Param::new("text", "string", true, "The text to search"),
+        Param::new(
+            "pattern",
+            "string",
+            true,
+            "The regular expression, in Rust `regex` crate syntax",
+        ),
+        Param::new(
+            "all_matches",
+            "boolean",
+            false,
+            "Return all matches instead of only the first one. Defaults to false",
+        ),
+    ],
+    handler: Box::new(|_tools, params| -> Result<String, ToolError> {
+        let text = params["text"].as_str().unwrap();
+        let pattern = params["pattern"].as_str().unwrap();
+        let all_matches = params["all_matches"].as_bool().unwrap_or(false);
+        let regex = match regex::Regex::new(pattern) {
+            Ok(regex) => regex,
+            Err(e) => return Ok(json!({ "error": format!("invalid pattern: {}", e) }).to_string()),
+        };
+        let json = if all_matches {
+            let matches = regex
+                .captures_iter(text)
+                .map(|caps| regex_captures(&caps))
+                .collect::<Vec<_>>();
+            if matches.is_empty() {
+                json!({ "matched": false })
+            } else {
+                json!({ "all_matches": matches })
+            }
+        } else {
+            match regex.captures(text) {
+                Some(caps) => json!({ "matched": true, "captures": regex_captures(&caps) }),
+                None => json!({ "matched": false }),
+            }
+        };
+        Ok(json.to_string())
+    }),
+});
+
+fn hash_file<D: sha2::Digest>(path: &str) -> io::Result<String> {
+    let mut hasher = D::new();
+    let mut file = File::open(path)?;
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = io::Read::read(&mut file, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+static HASH_FILE: Lazy<GPTFunction> = Lazy::new(|| GPTFunction {
+    name: "hash_file",
+    desc: "Compute the checksum of a file",
+    params: vec![
+        Param::new("path", "string", true, "The file to hash"),
+        Param::new(
+            "algorithm",
+            "string",
+            true,
+            "One of \"md5\", \"sha1\", \"sha256\" or \"sha512\"",
+        ),
+    ],
+    handler: Box::new(|_tools, params| -> Result<String, ToolError> {
+        let path = params["path"].as_str().unwrap().trim();
+        let algorithm = params["algorithm"].as_str().unwrap().trim().to_lowercase();
+        let hash = match algorithm.as_str() {
+            "md5" => hash_file::<md5::Md5>(path),
+            "sha1" => hash_file::<sha1::Sha1>(path),
+            "sha256" => hash_file::<sha2::Sha256>(path),
+            "sha512" => hash_file::<sha2::Sha512>(path),
+            _ => {
+                let error = format!("unsupported algorithm: {}", algorithm);
+                return Ok(json!({ "error": error }).to_string());
+            }
+        };
+        let json = match hash {
+            Ok(hash) => json!({ "path": path, "algorithm": algorithm, "hash": hash }),
+            Err(e) => json!({ "error": format!("hash_file error: {}", e) }),
+        };
+        Ok(json.to_string())
+    }),
+});
+
+static SPELL_CHECK: Lazy<GPTFunction> = Lazy::new(|| GPTFunction {
+    name: "spell_check",
+    desc: "Check the spelling of a text and suggest corrections for misspelled words",
+    params: vec![
+        Param::new("text", "string", true, "The text to check"),
+        Param::new(
+            "language",
+            "string",
+            false,
+            "The language code, e.g. \"en\" or \"de\". Defaults to \"en\"",
+        ),
+    ],
+    handler: Box::new(|_tools, params| -> Result<String, ToolError> {
+        let text = params["text"].as_str().unwrap();
+        let language = params["language"].as_str().unwrap_or("en").trim();
+        let result = if which::which("aspell").is_ok() {
+            spell_check_with_aspell(text, language)
+        } else {
+            Ok(spell_check_with_word_list(text))
+        };
+        let json = match result {
+            Ok(corrections) => json!({
+                "error_count": corrections.len(),
+                "corrections": corrections
+                    .into_iter()
+                    .map(|(word, suggestions)| json!({ "word": word, "suggestions": suggestions }))
+                    .collect::<Vec<_>>(),
+            }),
+            Err(e) => json!({ "error": format!("spell_check error: {}", e) }),
+        };
+        Ok(json.to_string())
+    }),
+});
+
+/// Misspelled words and their suggestions, using `aspell`'s pipe mode
+fn spell_check_with_aspell(
+    text: &str,
+    language: &str,
+) -> anyhow::Result<Vec<(String, Vec<String>)>> {
+    let mut child = std::process::Command::new("aspell")
+        .arg("-a")
+        .arg(format!("--lang={}", language))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    // A leading `^` stops aspell from interpreting lines as commands
+    let input = text
+        .lines()
+        .map(|l| format!("^{}\n", l))
+        .collect::<String>();
+    // Write from another thread, so a large output can't block the input
+    std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        anyhow::bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    let corrections = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            // `& word count offset: a, b, c` or `# word offset`
+            let mut parts = line.splitn(2, ": ");
+            let head = parts.next()?;
+            let mut words = head.split_whitespace();
+            match words.next()? {
+                "&" => Some((
+                    words.next()?.to_owned(),
+                    parts.next()?.split(", ").map(|s| s.to_owned()).collect(),
+                )),
+                "#" => Some((words.next()?.to_owned(), vec![])),
+                _ => None,
+            }
+        })
+        .collect();
+    Ok(corrections)
+}
+
+/// English words and their frequencies, one `word count` per line (from SymSpell, MIT licensed)
+const WORD_FREQUENCIES: &str = include_str!("../assets/frequency_dictionary_en_82_765.txt");
+
+/// Misspelled English words, using the bundled word list when `aspell` is not installed
+fn spell_check_with_word_list(text: &str) -> Vec<(String, Vec<String>)> {
+    let n_words = WORD_FREQUENCIES
+        .lines()
+        .filter_map(|line| {
+            let (word, count) = line.trim_end().split_once(' ')?;
+            // The counts are from Google Books, and the largest don't fit in a u32
+            let count = count.parse::<u64>().ok()? / 10;
+            Some((word.to_owned(), u32::try_from(count).unwrap_or(u32::MAX)))
+        })
+        .collect();
+    let mut speller = spellcheck::Speller {
+        letters: "abcdefghijklmnopqrstuvwxyz".to_owned(),
+        n_words,
+    };
+    let mut corrections: Vec<(String, Vec<String>)> = vec![];
+    for word in text.split(|c: char| !c.is_ascii_alphabetic()) {
+        let lowercase = word.to_lowercase();
+        if word.len() < 2
+            || speller.n_words.contains_key(&lowercase)
+            || corrections.iter().any(|(w, _)| w == word)
+        {
+            continue;
+        }
+        let correction = speller.correct(&lowercase);
+        let suggestions = if correction == lowercase {
+            vec![]
+        } else {
+            vec![correction]
+        };
+        corrections.push((word.to_owned(), suggestions));
+    }
+    corrections
+}
+
+static MEMORY_STORE: Lazy<GPTFunction> = Lazy::new(|| {
+    GPTFunction {
+    name: "memory_store",
+    desc: "Save a note that persists across sessions, e.g. user preferences or project notes. Overwrites any existing note with the same key",
+    params: vec![
+        Param::new("key", "string", true, "The name of the note"),
+        Param::new("value", "string", true, "The content of the note"),
+    ],
+    handler: Box::new(|tools, params| -> Result<String, ToolError> {
+        let key = params["key"].as_str().unwrap().trim();
+        let value = params["value"].as_str().unwrap();
+        outputln!("{} {}", "➜".blue().bold(), format!("remember {key}").bold());
+        if tools.dry_run.load(Ordering::SeqCst) {
+            return Ok(dry_run_result(json!({ "stored": true })));
+        }
+        let result = load_memory().and_then(|mut memory| {
+            memory.insert(key.to_owned(), value.to_owned());
+            save_memory(&memory)
+        });
+        let json = match result {
+            Ok(_) => json!({ "stored": true }),
+            Err(e) => json!({ "error": format!("memory_store error: {}", e) }),
+        };
+        Ok(json.to_string())
+    }),
+}
+});
+
+static MEMORY_RECALL: Lazy<GPTFunction> = Lazy::new(|| GPTFunction {
+    name: "memory_recall",
+    desc: "Read a note saved with memory_store, or list the keys of all notes",
+    params: vec![Param::new(
+        "key",
+        "string",
+        false,
+        "The name of the note. If not provided, returns all keys",
+    )],
+    handler: Box::new(|_tools, params| -> Result<String, ToolError> {
+        let key = params["key"].as_str().map(|k| k.trim());
+        let json = match load_memory() {
+            Ok(memory) => match key {
+                Some(key) => match memory.get(key) {
+                    Some(value) => json!({ "value": value, "found": true }),
+                    None => json!({ "found": false }),
+                },
+                None => json!({ "keys": memory.keys().collect::<Vec<_>>() }),
+            },
+            Err(e) => json!({ "error": format!("memory_recall error: {}", e) }),
+        };
+        Ok(json.to_string())
+    }),
+});
+
+static MEMORY_DELETE: Lazy<GPTFunction> = Lazy::new(|| GPTFunction {
+    name: "memory_delete",
+    desc: "Delete a note saved with memory_store",
+    params: vec![Param::new("key", "string", true, "The name of the note")],
+    handler: Box::new(|tools, params| -> Result<String, ToolError> {
+        let key = params["key"].as_str().unwrap().trim();
+        outputln!("{} {}", "➜".blue().bold(), format!("forget {key}").bold());
+        if tools.dry_run.load(Ordering::SeqCst) {
+            return Ok(dry_run_result(json!({ "deleted": true })));
+        }
+        let result = load_memory().and_then(|mut memory| {
+            let deleted = memory.remove(key).is_some();
+            save_memory(&memory)?;
+            Ok(deleted)
+        });
+        let json = match result {
+            Ok(deleted) => json!({ "deleted": deleted }),
+            Err(e) => json!({ "error": format!("memory_delete error: {}", e) }),
+        };
+        Ok(json.to_string())
+    }),
+});
+
+/// Path of the notes saved by the memory tools: `~/.config/gptsh/memory.json`
+fn memory_path() -> anyhow::Result<PathBuf> {
+    Ok(Config::path()?.with_file_name("memory.json"))
+}
+
+fn load_memory() -> anyhow::Result<std::collections::BTreeMap<String, String>> {
+    match std::fs::read_to_string(memory_path()?) {
+        Ok(content) => Ok(serde_json::from_str(&content)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Default::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn save_memory(memory: &std::collections::BTreeMap<String, String>) -> anyhow::Result<()> {
+    std::fs::write(memory_path()?, serde_json::to_string_pretty(memory)?)?;
+    Ok(())
+}
+
+static DATE_TIME: Lazy<GPTFunction> = Lazy::new(|| GPTFunction {
+    name: "date_time",
+    desc: "Get the current local date, time and timezone",
+    params: vec![Param::new(
+        "format",
+        "string",
+        false,
+        "A strftime-style format string. Defaults to \"%Y-%m-%dT%H:%M:%S%z\"",
+    )],
+    handler: Box::new(|_tools, params| -> Result<String, ToolError> {
+        use chrono::format::{Item, StrftimeItems};
+        let format = params["format"].as_str().unwrap_or("%Y-%m-%dT%H:%M:%S%z");
+        let items = StrftimeItems::new(format).collect::<Vec<_>>();
+        if items.iter().any(|item| matches!(item, Item::Error)) {
+            let error = format!("invalid format string: {}", format);
+            return Ok(json!({ "error": error }).to_string());
+        }
+        let now = chrono::Local::now();
+        let json = json!({
+            "datetime": now.format_with_items(items.into_iter()).to_string(),
+            "timezone": iana_time_zone::get_timezone().unwrap_or_else(|_| now.format("%:z").to_string()),
+            "unix_timestamp": now.timestamp(),
+        });
+        Ok(json.to_string())
+    }),
+});
+
+#[cfg(unix)]
+static PROCESS_KILL: Lazy<GPTFunction> = Lazy::new(|| GPTFunction {
+    name: "process_kill",
+    desc: "Send a signal to a process by PID",
+    params: vec![
+        Param::new("pid", "integer", true, "The process ID"),
+        Param::new(
+            "signal",
+            "string",
+            false,
+            "One of \"TERM\", \"KILL\", \"HUP\" or \"INT\". Defaults to \"TERM\"",
+        ),
+    ],
+    handler: Box::new(|tools, params| -> Result<String, ToolError> {
+        use nix::sys::signal::{kill, Signal};
+        use nix::unistd::Pid;
+        let pid = params["pid"].as_i64().unwrap_or(0);
+        let signal_name = params["signal"].as_str().unwrap_or("TERM").trim();
+        let signal_name = signal_name.trim_start_matches("SIG").to_uppercase();
+        outputln!(
+            "{} {}",
+            "➜".red().bold(),
+            format!("kill -{signal_name} {pid}").bold()
+        );
+        let signal = match signal_name.as_str() {
+            "TERM" => Signal::SIGTERM,
+            "KILL" => Signal::SIGKILL,
+            "HUP" => Signal::SIGHUP,
+            "INT" => Signal::SIGINT,
+            _ => {
+                let error = format!("unsupported signal: {}", signal_name);
+                return Ok(json!({ "error": error }).to_string());
+            }
+        };
+        // Only positive PIDs, so a single process is targeted rather than a process group
+        if pid <= 0 || pid > i32::MAX as i64 {
+            return Ok(json!({ "error": format!("invalid pid: {}", pid) }).to_string());
+        }
+        let pid = Pid::from_raw(pid as i32);
+        if tools.dry_run.load(Ordering::SeqCst) {
+            let json = json!({ "pid": pid.as_raw(), "signal": signal_name, "ok": true });
+            return Ok(dry_run_result(json));
+        }
+        // Always confirm, even with `--yes`
+        if !utils::stdin_is_terminal() || !utils::wait_for_user_acknowledgement() {
+            return Err(ToolError::UserCancelled);
+        }
+        let json = match kill(pid, signal) {
+            Ok(_) => json!({ "pid": pid.as_raw(), "signal": signal_name, "ok": true }),
+            Err(e) => json!({ "error": format!("process_kill error: {}", e) }),
+        };
+        Ok(json.to_string())
+    }),
+});
+
+impl Tools {
+    /// The default tool set, with the `[permissions]` and `[session]` settings of `config`
+    pub fn from_config(config: &Config) -> Self {
+        let mut tools: Vec<&'static GPTFunction> = vec![];
+        if config.permissions.bash {
+            tools.push(&RUN_COMMAND);
+        }
+        tools.extend([
+            &*CHANGE_CWD,
+            &*LIST_DIRECTORY,
+            &*READ_FILE,
+            &*WRITE_FILE,
+            &*WRITE_TEMP_FILE,
+            &*COMPRESS_FILE,
+            &*DELETE_FILE,
+            &*JSON_PATH,
+            &*YAML_TO_JSON,
+            &*JSON_TO_YAML,
+            &*TEMPLATE_FILE,
+            &*FORMAT_CODE,
+            &*LINT_CODE,
+            &*SLEEP,
+            &*ENCODE_DECODE,
+            &*REGEX_MATCH,
+            &*HASH_FILE,
+            &*DATE_TIME,
+            &*SPELL_CHECK,
+            &*MEMORY_STORE,
+            &*MEMORY_RECALL,
+            &*MEMORY_DELETE,
+            // Add more tools here
+        ]);
+        #[cfg(unix)]
+        tools.push(&PROCESS_KILL);
+        let mut tools = Tools::new(&tools);
+        tools.permissions = config.permissions.clone();
+        tools.max_output_chars = config.session.max_tool_output_chars;
+        tools.sensitive_env_patterns = config.session.sensitive_env_patterns.clone();
+        tools.confirm_all_commands = config.ui.confirm_all_commands;
+        tools
+    }
+}
@@ -1,102 +1,401 @@
 use std::{
+    future::Future,
     io::{self, IsTerminal, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
     sync::Mutex,
+    time::{Duration, Instant},
 };
 
 use colored::Colorize;
 use crossterm::event::{self, Event, KeyCode, KeyEvent};
-use once_cell::sync::Lazy;
+use once_cell::sync::{Lazy, OnceCell};
+use regex::Regex;
 use rustyline::error::ReadlineError;
 use rustyline::DefaultEditor;
 
-use crate::utils;
+use crate::config::{Config, UIConfig};
+use crate::{output, outputln, utils};
 
 pub fn get_cwd_short_form() -> String {
     let cwd = std::env::current_dir().unwrap();
-    let home_dir = home::home_dir().unwrap();
-    let cwd = cwd.to_str().unwrap();
-    let home_dir = home_dir.to_str().unwrap();
-    let simplified_home_dir = if cwd.starts_with(home_dir) {
-        cwd.replacen(home_dir, "~", 1)
-    } else {
-        cwd.to_string()
-    };
-    let simplified_home_dir = PathBuf::from(simplified_home_dir);
-    let mut segments = vec![];
-    let num_segments = simplified_home_dir.components().count();
+    short_form(&cwd, home::home_dir().as_deref())
+}
 
-    for (i, c) in simplified_home_dir.components().enumerate() {
-        if i == num_segments - 1 {
-            segments.push(c.as_os_str().to_str().unwrap().to_string());
-        } else {
-            segments.push(
-                c.as_os_str()
-                    .to_str()
-                    .unwrap()
-                    .chars()
-                    .next()
-                    .unwrap()
-                    .to_string(),
-            );
+/// Abbreviate all but the last directory to one letter, e.g. `~/p/gptsh` or `/u/local`.
+/// On Windows the drive letter (`C:`) or UNC prefix (`\\server\share`) is kept intact.
+fn short_form(cwd: &Path, home: Option<&Path>) -> String {
+    use std::path::{Component, MAIN_SEPARATOR_STR};
+    let (mut segments, rest) = match home.and_then(|h| cwd.strip_prefix(h).ok()) {
+        Some(rest) => (vec!["~".to_owned()], rest),
+        None => (vec![], cwd),
+    };
+    let components = rest
+        .components()
+        .filter(|c| !matches!(c, Component::RootDir))
+        .collect::<Vec<_>>();
+    for (i, c) in components.iter().enumerate() {
+        let name = c.as_os_str().to_string_lossy();
+        match c {
+            Component::Prefix(_) => segments.push(name.trim_end_matches('\\').to_string()),
+            _ if i == components.len() - 1 => segments.push(name.to_string()),
+            _ => segments.push(name.chars().next().unwrap().to_string()),
         }
     }
-
-    let s = segments.join("/");
-    if !s.starts_with('~') {
-        format!("/{}", s)
-    } else {
+    let s = segments.join(MAIN_SEPARATOR_STR);
+    if cfg!(windows) || segments.first().is_some_and(|s| s == "~") {
         s
+    } else {
+        format!("/{}", s)
+    }
+}
+
+/// Expand a leading `~` to the home directory
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), home::home_dir()) {
+        (Ok(rest), Some(home_dir)) => home_dir.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Clear the screen on `Ctrl+L`, then redraw the prompt
+struct ClearScreenHandler;
+
+impl rustyline::ConditionalEventHandler for ClearScreenHandler {
+    fn handle(
+        &self,
+        _evt: &rustyline::Event,
+        _n: rustyline::RepeatCount,
+        _positive: bool,
+        _ctx: &rustyline::EventContext,
+    ) -> Option<rustyline::Cmd> {
+        crossterm::execute!(
+            io::stdout(),
+            crossterm::terminal::Clear(crossterm::terminal::ClearType::All),
+            crossterm::cursor::MoveTo(0, 0)
+        )
+        .ok()?;
+        Some(rustyline::Cmd::Repaint)
     }
 }
 
-pub fn read_user_prompt() -> anyhow::Result<Option<String>> {
-    static EDITOR: Lazy<Mutex<DefaultEditor>> =
-        Lazy::new(|| Mutex::new(DefaultEditor::new().unwrap()));
-    let mut rl = EDITOR.lock().unwrap();
+/// Read a prompt from the REPL. A line ending with `\` continues on the next line,
+/// and Alt+Enter inserts a newline. Ctrl+C on a continuation line discards the whole prompt.
+/// History is saved to `~/.config/gptsh/repl_history`, and the history options are read from the first call's `ui`.
+pub fn read_user_prompt(ui: &UIConfig) -> anyhow::Result<Option<String>> {
+    static HISTORY_FILE: Lazy<Option<PathBuf>> =
+        Lazy::new(|| Some(Config::path().ok()?.with_file_name("repl_history")));
+    static EDITOR: OnceCell<Mutex<DefaultEditor>> = OnceCell::new();
+    let editor = EDITOR.get_or_init(|| {
+        let config = rustyline::Config::builder()
+            .max_history_size(ui.history_size)
+            .and_then(|b| b.history_ignore_dups(ui.history_ignore_dups))
+            .map(|b| b.build())
+            .unwrap_or_default();
+        let mut editor = DefaultEditor::with_config(config).unwrap();
+        if let Some(history_file) = HISTORY_FILE.as_ref() {
+            let _ = editor.load_history(history_file);
+        }
+        editor.bind_sequence(
+            rustyline::KeyEvent::ctrl('L'),
+            rustyline::EventHandler::Conditional(Box::new(ClearScreenHandler)),
+        );
+        editor.bind_sequence(
+            rustyline::KeyEvent(rustyline::KeyCode::Enter, rustyline::Modifiers::ALT),
+            rustyline::Cmd::Newline,
+        );
+        Mutex::new(editor)
+    });
+    let mut rl = editor.lock().unwrap();
     let prompt = format!(
         "{}{} ",
         get_cwd_short_form().bold().on_blue().white(),
         "\u{e0b0}".blue()
     );
-    match rl.readline(&prompt) {
-        Ok(line) => {
-            let _ = rl.add_history_entry(line.as_str());
-            Ok(Some(line))
+    let mut buffer = String::new();
+    loop {
+        let continuation = !buffer.is_empty();
+        let line = match rl.readline(if continuation { "… " } else { &prompt }) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) if continuation => return Ok(Some(String::new())),
+            Err(ReadlineError::Eof) if continuation => return Ok(Some(buffer)),
+            Err(ReadlineError::Eof) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        match line.strip_suffix('\\') {
+            Some(line) => {
+                buffer.push_str(line);
+                buffer.push('\n');
+            }
+            None => {
+                buffer.push_str(&line);
+                break;
+            }
+        }
+    }
+    if rl.add_history_entry(buffer.as_str()).unwrap_or(false) {
+        if let Some(history_file) = HISTORY_FILE.as_ref() {
+            let _ = rl.save_history(history_file);
         }
-        Err(ReadlineError::Eof) => Ok(None),
-        Err(e) => Err(e.into()),
     }
+    if buffer.trim() == "/edit" {
+        return read_prompt_from_editor().map(Some);
+    }
+    Ok(Some(buffer))
+}
+
+/// Open `$EDITOR` on a temporary file and return its content once the editor exits
+fn read_prompt_from_editor() -> anyhow::Result<String> {
+    let file = tempfile::NamedTempFile::new()?;
+    open_in_editor(file.path())?;
+    Ok(std::fs::read_to_string(file.path())?)
+}
+
+/// Open a file in `$EDITOR` (defaults to `nano`) and wait for the editor to exit
+pub fn open_in_editor(path: &Path) -> anyhow::Result<()> {
+    let editor = std::env::var("EDITOR").unwrap_or_else(|_| "nano".to_string());
+    let mut words = shellwords::split(&editor)?;
+    if words.is_empty() {
+        words.push("nano".to_string());
+    }
+    let status = std::process::Command::new(&words[0])
+        .args(&words[1..])
+        .arg(path)
+        .status()?;
+    if !status.success() {
+        anyhow::bail!("{} exited with {}", editor, status);
+    }
+    Ok(())
 }
 
 pub fn wait_for_user_acknowledgement() -> bool {
     let s = format!("[{}] Confirm • [{}] Abort", "ENTER↵".green(), "^c".red())
         .white()
         .on_bright_black();
-    print!("{}", &s);
-    io::stdout().flush().unwrap();
-    crossterm::terminal::enable_raw_mode().unwrap();
-    let mut abort = false;
-    while let Event::Key(KeyEvent {
+    let abort = wait_for_key(&s.to_string(), false) != Some(KeyCode::Enter);
+    if abort {
+        outputln!("{}", "Aborted.".red());
+    }
+    !abort
+}
+
+/// Like `wait_for_user_acknowledgement`, but `e` lets the user edit the command first.
+/// Returns the command to run, or `None` if aborted.
+pub fn wait_for_user_acknowledgement_or_edit(command: &str) -> Option<String> {
+    let s = format!(
+        "[{}] Confirm • [{}] Edit • [{}] Abort",
+        "ENTER↵".green(),
+        "e".yellow(),
+        "^c".red()
+    )
+    .white()
+    .on_bright_black();
+    let command = match wait_for_key(&s.to_string(), true) {
+        Some(KeyCode::Enter) => Some(command.to_owned()),
+        Some(KeyCode::Char('e')) => DefaultEditor::new()
+            .ok()
+            .and_then(|mut rl| rl.readline_with_initial("✎ ", (command, "")).ok())
+            .filter(|c| !c.trim().is_empty()),
+        _ => None,
+    };
+    if command.is_none() {
+        outputln!("{}", "Aborted.".red());
+    }
+    command
+}
+
+/// Show a hint and wait for ENTER, Ctrl+C or (if `allow_edit`) `e`. Returns `None` on Ctrl+C.
+fn wait_for_key(hint: &str, allow_edit: bool) -> Option<KeyCode> {
+    output!("{}", hint);
+    // Without a terminal to confirm on (e.g. piped input with `confirm_all_commands`), abort
+    if crossterm::terminal::enable_raw_mode().is_err() {
+        outputln!();
+        return None;
+    }
+    let mut key = None;
+    while let Ok(Event::Key(KeyEvent {
         code, modifiers, ..
-    }) = event::read().unwrap()
+    })) = event::read()
     {
-        if code == KeyCode::Enter {
+        if code == KeyCode::Enter || (allow_edit && code == KeyCode::Char('e')) {
+            key = Some(code);
             break;
         } else if code == KeyCode::Char('c') && modifiers == event::KeyModifiers::CONTROL {
-            abort = true;
             break;
         }
     }
     crossterm::terminal::disable_raw_mode().unwrap();
-    let back = s.as_bytes().iter().map(|_| "\u{8}").collect::<String>();
-    let ws = s.as_bytes().iter().map(|_| " ").collect::<String>();
-    print!("{}{}{}", back, ws, back);
-    io::stdout().flush().unwrap();
-    if abort {
-        println!("{}", "Aborted.".red());
+    let back = hint.as_bytes().iter().map(|_| "\u{8}").collect::<String>();
+    let ws = hint.as_bytes().iter().map(|_| " ").collect::<String>();
+    output!("{}{}{}", back, ws, back);
+    key
+}
+
+/// Ask the user to type `expected` to confirm a destructive action
+pub fn wait_for_user_typed_confirmation(expected: &str) -> bool {
+    if !stdin_is_terminal() {
+        return false;
     }
-    !abort
+    output!(
+        "{} ",
+        format!("Type '{}' to confirm:", expected)
+            .white()
+            .on_bright_black()
+    );
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line).is_err() || line.trim() != expected {
+        outputln!("{}", "Aborted.".red());
+        return false;
+    }
+    true
+}
+
+/// Format a duration as a short human-readable string, e.g. `1h 2m`, `2m 34s`, `12s`, `450ms`
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs >= 3600 {
+        format!("{}h {}m", secs / 3600, (secs % 3600) / 60)
+    } else if secs >= 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else if secs >= 1 {
+        format!("{}s", secs)
+    } else if d.as_millis() >= 1 {
+        format!("{}ms", d.as_millis())
+    } else {
+        "<1ms".to_string()
+    }
+}
+
+/// Whether `GPTSH_PERF=1` is set, to print timings with [`measure_time`]
+static PERF_ENABLED: Lazy<bool> = Lazy::new(|| std::env::var("GPTSH_PERF").is_ok_and(|v| v == "1"));
+
+/// Run `f`, and print how long it took to stderr if `GPTSH_PERF=1` is set
+pub fn measure_time<F, R>(label: &str, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    if !*PERF_ENABLED {
+        return f();
+    }
+    let start = Instant::now();
+    let result = f();
+    eprintln!("[PERF] {}: {}ms", label, start.elapsed().as_millis());
+    result
+}
+
+/// [`measure_time`] for futures
+pub async fn measure_time_async<F: Future>(label: &str, f: F) -> F::Output {
+    if !*PERF_ENABLED {
+        return f.await;
+    }
+    let start = Instant::now();
+    let result = f.await;
+    eprintln!("[PERF] {}: {}ms", label, start.elapsed().as_millis());
+    result
+}
+
+/// Keep the head and tail of a text that is longer than `max_chars`, with a marker in the middle
+pub fn truncate_middle(text: &str, max_chars: usize) -> String {
+    let chars = text.chars().count();
+    if chars <= max_chars {
+        return text.to_owned();
+    }
+    let head = text.chars().take(max_chars / 2).collect::<String>();
+    let tail_start = text
+        .char_indices()
+        .nth(chars - (max_chars - max_chars / 2))
+        .map_or(text.len(), |(i, _)| i);
+    let tail = &text[tail_start..];
+    let truncated_bytes = text.len() - head.len() - tail.len();
+    format!("{head}\n...[truncated {truncated_bytes} bytes]...\n{tail}")
+}
+
+/// Roughly estimate the number of tokens in a text (~4 characters per token)
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Count the tokens in a text with the tokenizer of `model`.
+/// Falls back to [`estimate_tokens`] for unknown models.
+pub fn count_tokens(text: &str, model: &str) -> usize {
+    match tiktoken_rs::bpe_for_model(model) {
+        Ok(bpe) => bpe.encode_ordinary(text).len(),
+        Err(_) => estimate_tokens(text),
+    }
+}
+
+/// The context window size (in tokens) of an OpenAI model
+pub fn context_window_size(model: &str) -> usize {
+    match model {
+        m if m.starts_with("gpt-4o") || m.starts_with("gpt-4-turbo") => 128000,
+        m if m.starts_with("gpt-4-1106") || m.starts_with("gpt-4-0125") => 128000,
+        m if m.starts_with("gpt-4-32k") => 32768,
+        m if m.starts_with("gpt-4") => 8192,
+        m if m.starts_with("gpt-3.5-turbo-instruct") => 4096,
+        m if m.starts_with("gpt-3.5-turbo") => 16385,
+        _ => 4096,
+    }
+}
+
+/// The price in USD per million input and output tokens of an OpenAI model, if known
+pub fn model_price_per_million_tokens(model: &str) -> Option<(f64, f64)> {
+    match model {
+        m if m.starts_with("gpt-4o-mini") => Some((0.15, 0.60)),
+        m if m.starts_with("gpt-4o") => Some((2.50, 10.00)),
+        m if m.starts_with("gpt-4-turbo") => Some((10.00, 30.00)),
+        m if m.starts_with("gpt-4-1106") || m.starts_with("gpt-4-0125") => Some((10.00, 30.00)),
+        m if m.starts_with("gpt-4-32k") => Some((60.00, 120.00)),
+        m if m.starts_with("gpt-4") => Some((30.00, 60.00)),
+        m if m.starts_with("gpt-3.5-turbo") => Some((0.50, 1.50)),
+        _ => None,
+    }
+}
+
+/// Whether the terminal supports OSC 8 hyperlinks
+pub fn terminal_supports_hyperlinks() -> bool {
+    std::env::var_os("TERM_PROGRAM").is_some() || std::env::var_os("VTE_VERSION").is_some()
+}
+
+/// Replace markdown images `![alt](url)` with clickable hyperlinks, or `[image: url]` if hyperlinks are not supported
+pub fn render_markdown_images(content: &str) -> String {
+    static IMAGE: Lazy<Regex> = Lazy::new(|| Regex::new(r"!\[([^\]]*)\]\(([^)\s]+)\)").unwrap());
+    let hyperlinks = terminal_supports_hyperlinks();
+    IMAGE
+        .replace_all(content, |caps: &regex::Captures| {
+            let (alt, url) = (&caps[1], &caps[2]);
+            if hyperlinks {
+                // Terminated by BEL rather than ST, as termimad treats `\` as an escape
+                format!("\x1b]8;;{}\x07[🖼 {}]\x1b]8;;\x07", url, alt)
+            } else {
+                format!("[image: {}]", url)
+            }
+        })
+        .into_owned()
+}
+
+/// Remove common markdown syntax, leaving plain text
+pub fn strip_markdown(content: &str) -> String {
+    static RULES: Lazy<Vec<(Regex, &str)>> = Lazy::new(|| {
+        [
+            (r"(?m)^```.*$\n?", ""),
+            (r"(?m)^\s{0,3}#{1,6}\s+", ""),
+            (r"(?m)^\s{0,3}>\s?", ""),
+            (r"(?m)^(\s*)[*+]\s+", "$1- "),
+            (r"!\[([^\]]*)\]\(([^)\s]+)\)", "$1 ($2)"),
+            (r"\[([^\]]+)\]\(([^)\s]+)\)", "$1 ($2)"),
+            (r"\*\*([^*]+)\*\*|__([^_]+)__", "$1$2"),
+            (r"\*([^*\s][^*]*)\*", "$1"),
+            (r"`([^`]+)`", "$1"),
+        ]
+        .iter()
+        .map(|(pattern, replacement)| (Regex::new(pattern).unwrap(), *replacement))
+        .collect()
+    });
+    let mut content = content.to_owned();
+    for (regex, replacement) in RULES.iter() {
+        content = regex.replace_all(&content, *replacement).into_owned();
+    }
+    content
 }
 
 /// Check if the inputs are coming from a terminal
@@ -104,18 +403,65 @@ pub fn stdin_is_terminal() -> bool {
     io::stdin().is_terminal()
 }
 
+/// Copy text to the system clipboard with the OSC 52 terminal escape sequence.
+/// Works over SSH, in terminals that support it.
+pub fn copy_to_clipboard(text: &str) -> io::Result<()> {
+    use base64::Engine;
+    let encoded = base64::engine::general_purpose::STANDARD.encode(text);
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b]52;c;{}\x07", encoded)?;
+    stdout.flush()
+}
+
+/// Whether gptsh's own output goes to stderr, so stdout only has the responses (`--format json`)
+static OUTPUT_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+pub fn set_output_to_stderr(enabled: bool) {
+    OUTPUT_TO_STDERR.store(enabled, Ordering::SeqCst);
+}
+
+pub fn output_to_stderr() -> bool {
+    OUTPUT_TO_STDERR.load(Ordering::SeqCst)
+}
+
+/// `println!` for gptsh's own output, such as commands and their output, as opposed to responses.
+/// Goes to stderr with `--format json`.
+#[macro_export]
+macro_rules! outputln {
+    ($($arg:tt)*) => {
+        if $crate::utils::output_to_stderr() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+/// Like [`outputln!`], without the newline, and flushed immediately
+#[macro_export]
+macro_rules! output {
+    ($($arg:tt)*) => {
+        if $crate::utils::output_to_stderr() {
+            eprint!($($arg)*);
+        } else {
+            print!($($arg)*);
+            let _ = std::io::Write::flush(&mut std::io::stdout());
+        }
+    };
+}
+
 pub fn stdout_is_terminal() -> bool {
     io::stdout().is_terminal()
 }
 
-pub fn print_banner(repl: bool) {
+pub fn print_banner(repl: bool, warn_root: bool) {
     if repl && utils::stdin_is_terminal() {
-        println!(
+        outputln!(
             "🦄 Welcome to {}. The AI-powered, noob-friendly interactive shell.",
             "gptsh".blue().bold()
         );
     }
-    if whoami::username() == "root" {
+    if warn_root && whoami::username() == "root" {
         eprintln!(
             "🚨 {}",
             "WARNING: Running as root is dangerous and is not recommended!"
@@ -124,3 +470,100 @@ pub fn print_banner(repl: bool) {
         );
     }
 }
+
+/// A spinner animation on the current terminal line, drawn from a background thread
+/// until it is stopped or dropped.
+pub struct Spinner {
+    stop: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Spinner {
+    /// Start a spinner with one of the `ui.spinner_style` animations. Returns `None` if stdout is not a terminal.
+    pub fn start(style: &str, message: &str) -> Option<Self> {
+        if !stdout_is_terminal() || output_to_stderr() {
+            return None;
+        }
+        let frames: &[&str] = match style {
+            "line" => &["-", "\\", "|", "/"],
+            "bounce" => &["⠁", "⠂", "⠄", "⠂"],
+            "arrow" => &["←", "↖", "↑", "↗", "→", "↘", "↓", "↙"],
+            _ => &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"],
+        };
+        let stop = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let message = message.to_owned();
+        let thread = std::thread::spawn({
+            let stop = stop.clone();
+            move || {
+                let mut stdout = io::stdout();
+                let _ = crossterm::execute!(stdout, crossterm::cursor::Hide);
+                for frame in frames.iter().cycle() {
+                    if stop.load(std::sync::atomic::Ordering::SeqCst) {
+                        break;
+                    }
+                    let _ = write!(
+                        stdout,
+                        "\r{} {}",
+                        frame.blue().bold(),
+                        message.bright_black()
+                    );
+                    let _ = stdout.flush();
+                    std::thread::sleep(Duration::from_millis(80));
+                }
+                let _ = crossterm::execute!(
+                    stdout,
+                    crossterm::terminal::Clear(crossterm::terminal::ClearType::CurrentLine),
+                    crossterm::cursor::MoveToColumn(0),
+                    crossterm::cursor::Show
+                );
+            }
+        });
+        Some(Self {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Spinner {
+    /// Stop the animation and clear the line, so no artifacts are left behind
+    fn drop(&mut self) {
+        self.stop.store(true, std::sync::atomic::Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(windows))]
+    #[test]
+    fn short_form_unix() {
+        let home = PathBuf::from("/home/alice");
+        let short_form = |cwd: &str| super::short_form(Path::new(cwd), Some(&home));
+        assert_eq!(short_form("/home/alice"), "~");
+        assert_eq!(short_form("/home/alice/projects/gptsh"), "~/p/gptsh");
+        assert_eq!(short_form("/usr/local/bin"), "/u/l/bin");
+        assert_eq!(short_form("/"), "/");
+        // Only whole components of the home directory are replaced
+        assert_eq!(short_form("/home/alice2/src"), "/h/a/src");
+        assert_eq!(super::short_form(Path::new("/tmp"), None), "/tmp");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn short_form_windows() {
+        let home = PathBuf::from(r"C:\Users\alice");
+        let short_form = |cwd: &str| super::short_form(Path::new(cwd), Some(&home));
+        assert_eq!(short_form(r"C:\Users\alice"), "~");
+        assert_eq!(short_form(r"C:\Users\alice\projects\gptsh"), r"~\p\gptsh");
+        assert_eq!(short_form(r"D:\work\src\gptsh"), r"D:\w\s\gptsh");
+        assert_eq!(
+            short_form(r"\\server\share\docs\notes"),
+            r"\\server\share\d\notes"
+        );
+    }
+}
@@ -0,0 +1,136 @@
+use std::io::Write;
+
+use async_openai::types::ChatCompletionRequestMessage;
+use gptsh::config::Config;
+use gptsh::session::ShellSession;
+use serde_json::json;
+
+/// A config that talks to a mock API server instead of OpenAI
+fn mock_config(server: &mockito::Server) -> Config {
+    let mut config = Config::default();
+    config.openai.api_key = Some("sk-test".to_owned());
+    config.openai.base_url = Some(server.url());
+    config.openai.max_retries = 0;
+    config.min_prompt_interval_ms = 0;
+    // Don't read or write the platform info cache in the home directory
+    config.session.platform_info = false;
+    config
+}
+
+fn mock_session(config: Config) -> ShellSession {
+    let mut session = ShellSession::with_config(config).unwrap();
+    session.yes = true;
+    session.quiet = true;
+    session.stream = false;
+    session
+}
+
+fn chat_completion(message: serde_json::Value) -> String {
+    json!({
+        "id": "chatcmpl-test",
+        "object": "chat.completion",
+        "created": 0,
+        "model": "gpt-3.5-turbo",
+        "choices": [{ "index": 0, "message": message, "finish_reason": "stop" }],
+        "usage": { "prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15 },
+    })
+    .to_string()
+}
+
+#[tokio::test]
+async fn run_prompt_round_trip() {
+    let mut server = mockito::Server::new_async().await;
+    let mock = server
+        .mock("POST", "/chat/completions")
+        .match_header("authorization", "Bearer sk-test")
+        .match_body(mockito::Matcher::PartialJson(json!({
+            "model": "gpt-3.5-turbo",
+            "messages": [{ "role": "system" }, { "role": "user", "content": "Say hello" }],
+        })))
+        .with_header("content-type", "application/json")
+        .with_body(chat_completion(
+            json!({ "role": "assistant", "content": "Hello!" }),
+        ))
+        .create_async()
+        .await;
+    let mut session = mock_session(mock_config(&server));
+    let response = session.run_prompt("Say hello").await.unwrap();
+    assert_eq!(response, "Hello!");
+    mock.assert_async().await;
+}
+
+#[tokio::test]
+async fn empty_choices_is_an_error() {
+    let mut server = mockito::Server::new_async().await;
+    server
+        .mock("POST", "/chat/completions")
+        .with_header("content-type", "application/json")
+        .with_body(
+            json!({
+                "id": "chatcmpl-test",
+                "object": "chat.completion",
+                "created": 0,
+                "model": "gpt-3.5-turbo",
+                "choices": [],
+            })
+            .to_string(),
+        )
+        .create_async()
+        .await;
+    let mut session = mock_session(mock_config(&server));
+    let error = session.run_prompt("Say hello").await.unwrap_err();
+    assert_eq!(error.to_string(), "API returned empty choices array");
+}
+
+fn tool_call(id: &str, name: &str, arguments: serde_json::Value) -> serde_json::Value {
+    json!({
+        "id": id,
+        "type": "function",
+        "function": { "name": name, "arguments": arguments.to_string() },
+    })
+}
+
+#[tokio::test]
+async fn tool_call_chain() {
+    // Each response has both text and a follow-up tool call, until the final answer
+    let responses = [
+        json!({
+            "role": "assistant",
+            "content": "Encoding first.",
+            "tool_calls": [tool_call("call_1", "encode_decode", json!({ "operation": "base64_encode", "input": "hi" }))],
+        }),
+        json!({
+            "role": "assistant",
+            "content": "Now decoding it back.",
+            "tool_calls": [tool_call("call_2", "encode_decode", json!({ "operation": "base64_decode", "input": "aGk=" }))],
+        }),
+        json!({ "role": "assistant", "content": "Round trip: hi" }),
+    ];
+    let mut fixture = tempfile::NamedTempFile::new().unwrap();
+    for response in responses {
+        writeln!(fixture, "{}", chat_completion(response)).unwrap();
+    }
+    let server = mockito::Server::new_async().await;
+    let mut session = mock_session(mock_config(&server));
+    // Strict, so a fourth request fails instead of reaching the mock server
+    session.simulate(fixture.path(), true).unwrap();
+    let response = session.run_prompt("Encode and decode hi").await.unwrap();
+    assert_eq!(response, "Round trip: hi");
+    let tool_results = session
+        .history()
+        .iter()
+        .filter_map(|m| match m {
+            ChatCompletionRequestMessage::Tool(t) => {
+                Some((t.tool_call_id.as_str(), t.content.as_str()))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        tool_results,
+        [
+            ("call_1", r#"{"result":"aGk="}"#),
+            ("call_2", r#"{"result":"hi"}"#),
+        ]
+    );
+}
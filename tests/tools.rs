@@ -0,0 +1,123 @@
+use gptsh::config::Config;
+use gptsh::tools::{ToolError, Tools};
+use serde_json::json;
+use std::sync::atomic::Ordering;
+
+fn offers(tools: &Tools, name: &str) -> bool {
+    tools.get_info().iter().any(|t| t.function.name == name)
+}
+
+#[test]
+fn run_command_is_offered_by_default() {
+    let tools = Tools::from_config(&Config::default());
+    assert!(offers(&tools, "run_command"));
+}
+
+#[test]
+fn run_command_is_not_offered_or_run_without_bash_permission() {
+    let mut config = Config::default();
+    config.permissions.bash = false;
+    let tools = Tools::from_config(&config);
+    assert!(!offers(&tools, "run_command"));
+    // Even if the model calls it anyway
+    let result = tools.run("run_command", json!({ "command": "echo hi" }));
+    assert!(matches!(result, Err(ToolError::ExecutionFailed(_))));
+}
+
+#[test]
+fn env_builtin_hides_sensitive_variables() {
+    std::env::set_var("GPTSH_TEST_API_KEY", "secret");
+    std::env::set_var("GPTSH_TEST_PRIVATE", "secret");
+    std::env::set_var("GPTSH_TEST_VISIBLE", "visible");
+    let mut config = Config::default();
+    config.session.sensitive_env_patterns = vec!["*_PRIVATE".to_owned()];
+    let tools = Tools::from_config(&config);
+    tools.yes.store(true, Ordering::SeqCst);
+    tools.quiet.store(true, Ordering::SeqCst);
+    let result = tools.run("run_command", json!({ "command": "env" }));
+    let result = serde_json::from_str::<serde_json::Value>(&result.ok().unwrap()).unwrap();
+    let stdout = result["stdout"].as_str().unwrap();
+    assert!(stdout.contains("GPTSH_TEST_VISIBLE=visible"));
+    assert!(!stdout.contains("secret"));
+}
+
+#[test]
+fn dry_run_does_not_write_or_delete_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("file.txt");
+    let tools = Tools::from_config(&Config::default());
+    tools.yes.store(true, Ordering::SeqCst);
+    tools.quiet.store(true, Ordering::SeqCst);
+    tools.dry_run.store(true, Ordering::SeqCst);
+    let args = json!({ "path": path, "content": "hello" });
+    let result = tools.run("write_file", args).ok().unwrap();
+    assert!(result.contains("\"dry_run\":true"));
+    assert!(!path.exists());
+    let args = json!({ "paths": [dir.path()], "output": path, "format": "zip" });
+    tools.run("compress_file", args).ok().unwrap();
+    assert!(!path.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn compress_file_does_not_follow_symlink_loops() {
+    let dir = tempfile::tempdir().unwrap();
+    let src = dir.path().join("src");
+    std::fs::create_dir(&src).unwrap();
+    std::fs::write(src.join("a.txt"), "a").unwrap();
+    std::os::unix::fs::symlink("..", src.join("parent")).unwrap();
+    let tools = Tools::from_config(&Config::default());
+    tools.yes.store(true, Ordering::SeqCst);
+    tools.quiet.store(true, Ordering::SeqCst);
+    for format in ["tar.gz", "zip"] {
+        let output = dir.path().join(format!("out.{}", format));
+        let args = json!({ "paths": [src], "output": output, "format": format });
+        let result = tools.run("compress_file", args).ok().unwrap();
+        let result = serde_json::from_str::<serde_json::Value>(&result).unwrap();
+        assert_eq!(result["files_added"], 2, "{}", result);
+    }
+}
+
+/// Restores the working directory when dropped, even if the test fails
+#[cfg(unix)]
+struct RestoreCwd(std::path::PathBuf);
+
+#[cfg(unix)]
+impl Drop for RestoreCwd {
+    fn drop(&mut self) {
+        let _ = std::env::set_current_dir(&self.0);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn run_command_keeps_directory_changes_and_exit_status() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path().canonicalize().unwrap();
+    let original = std::env::current_dir().unwrap();
+    let _restore = RestoreCwd(original.clone());
+    let tools = Tools::from_config(&Config::default());
+    tools.yes.store(true, Ordering::SeqCst);
+    tools.quiet.store(true, Ordering::SeqCst);
+    let run = |command: String| {
+        let result = tools.run("run_command", json!({ "command": command }));
+        serde_json::from_str::<serde_json::Value>(&result.ok().unwrap()).unwrap()
+    };
+    // The command's own EXIT trap must not stop the new directory from being recorded
+    let result = run(format!("cd '{}'; trap 'true' EXIT; false", dir.display()));
+    assert_eq!(result["status_code"], 1);
+    assert_eq!(std::env::current_dir().unwrap(), dir);
+    // `cd -` goes back to the directory before the command
+    run("cd -".to_owned());
+    assert_eq!(std::env::current_dir().unwrap(), original);
+}
+
+#[test]
+fn spell_check_works_without_system_word_lists() {
+    let tools = Tools::from_config(&Config::default());
+    let args = json!({ "text": "I recieve teh letter" });
+    let result = tools.run("spell_check", args).ok().unwrap();
+    let result = serde_json::from_str::<serde_json::Value>(&result).unwrap();
+    assert_eq!(result["error_count"], 2, "{}", result);
+    assert_eq!(result["corrections"][0]["word"], "recieve");
+}